@@ -0,0 +1,246 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use wallheaven_sync::{
+    config::{self, Naming},
+    storage::LinkMode,
+    wallheaven::link::Link,
+};
+
+/// Synchronize Wallhaven collections with a local storage directory.
+///
+/// Every value which is not passed as a flag is asked for interactively.
+#[derive(Parser, Debug)]
+#[command(name = "wallheaven_sync", version, about)]
+pub struct Cli {
+    /// Path to the config file, overrides WALLHEAVEN_SYNC_CONFIG
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Path to the storage directory, overrides WALLHEAVEN_SYNC_STORAGE_PATH
+    #[arg(long, short, global = true, value_name = "PATH")]
+    pub storage: Option<PathBuf>,
+
+    /// Wallhaven username which owns the collections
+    #[arg(long, short, global = true)]
+    pub username: Option<String>,
+
+    /// Number of wallpapers downloaded at the same time
+    #[arg(long, short, global = true, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs: Option<u16>,
+
+    /// Skip images larger than SIZE, in bytes or with K, M or G suffix ( example: 30M )
+    #[arg(long, global = true, value_name = "SIZE", value_parser = config::parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Answer yes to every confirmation prompt
+    #[arg(long, short, global = true)]
+    pub yes: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Download wallpapers missing from a local collection
+    Sync(SyncArgs),
+    /// Download all wallpapers declared in index.json which are missing on disk
+    Rebuild(CollectionArgs),
+    /// Remove files not declared in index.json and entries without a file
+    #[command(alias = "refresh")]
+    Prune(CollectionArgs),
+    /// Add new wallpaper to index.json
+    Add(AddArgs),
+    /// Check files of a collection against sizes and checksums in index.json
+    Verify(VerifyArgs),
+    /// Replace identical images across all collections with links to one file
+    Dedupe(DedupeArgs),
+    /// Rename files of a collection to a new naming and rewrite index.json
+    Rename(RenameArgs),
+    /// Mirror results of a Wallhaven search or uploads of a user into a local collection
+    Search(SearchArgs),
+    /// Sync or add what a link to the Wallhaven website points to
+    Fetch(FetchArgs),
+    /// List local or remote collections
+    List(ListArgs),
+    /// Print the resolved configuration and where every value came from
+    Config,
+}
+
+#[derive(Args, Debug, Default)]
+pub struct SyncArgs {
+    /// Label or id of the Wallhaven collection, can be repeated
+    #[arg(long, short)]
+    pub collection: Vec<String>,
+
+    /// Name of the saved search to refresh, can be repeated
+    #[arg(long)]
+    pub search: Vec<String>,
+
+    /// Synchronize every collection of the user and every saved search
+    #[arg(long, short, conflicts_with_all = ["collection", "search"])]
+    pub all: bool,
+
+    /// Label or id of the collection to leave out, can be repeated
+    #[arg(long, short)]
+    pub exclude: Vec<String>,
+
+    /// What to do with local wallpapers removed from the Wallhaven collection
+    #[arg(long, value_enum, default_value_t)]
+    pub deleted: DeletedAction,
+}
+
+/// Without any query flag the search saved in the config or by the previous run is used
+#[derive(Args, Debug)]
+pub struct SearchArgs {
+    /// Label of the local collection the results are stored in
+    pub name: String,
+
+    /// Search terms ( example: "nature -city" )
+    #[arg(long, short)]
+    pub query: Option<String>,
+
+    /// Mirror uploads of this Wallhaven user, every page unless limited
+    #[arg(long, value_name = "USERNAME")]
+    pub uploader: Option<String>,
+
+    /// Flags of general, anime and people categories ( example: 110 )
+    #[arg(long)]
+    pub categories: Option<String>,
+
+    /// Flags of sfw, sketchy and nsfw purity ( example: 100 )
+    #[arg(long)]
+    pub purity: Option<String>,
+
+    /// Minimal resolution ( example: 1920x1080 )
+    #[arg(long)]
+    pub atleast: Option<String>,
+
+    /// Comma separated aspect ratios ( example: 16x9,16x10 )
+    #[arg(long)]
+    pub ratios: Option<String>,
+
+    /// Hex code of the color without # ( example: 660000 )
+    #[arg(long)]
+    pub colors: Option<String>,
+
+    /// date_added, relevance, random, views, favorites or toplist
+    #[arg(long)]
+    pub sorting: Option<String>,
+
+    /// Period of the toplist ( example: 1M )
+    #[arg(long)]
+    pub top_range: Option<String>,
+
+    /// Fetch at most N pages of results, without any limit only the first page
+    /// or every page of uploads
+    #[arg(long, value_name = "N")]
+    pub max_pages: Option<u32>,
+
+    /// Fetch at most N wallpapers
+    #[arg(long, value_name = "N")]
+    pub max_wallpapers: Option<usize>,
+
+    /// What to do with local wallpapers which dropped out of the results
+    #[arg(long, value_enum, default_value_t)]
+    pub deleted: DeletedAction,
+}
+
+#[derive(Args, Debug)]
+pub struct FetchArgs {
+    /// Link to a search, toplist, favorites collection or uploads of a user, or a wallpaper
+    /// ( example: https://wallhaven.cc/user/NAME/favorites/123 )
+    pub link: Link,
+
+    /// Label of the local collection, defaults to the label of the Wallhaven collection
+    #[arg(long, short)]
+    pub collection: Option<String>,
+
+    /// Fetch at most N pages of search results
+    #[arg(long, value_name = "N")]
+    pub max_pages: Option<u32>,
+
+    /// Fetch at most N wallpapers of search results
+    #[arg(long, value_name = "N")]
+    pub max_wallpapers: Option<usize>,
+
+    /// What to do with local wallpapers removed from the collection or the results
+    #[arg(long, value_enum, default_value_t)]
+    pub deleted: DeletedAction,
+}
+
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq)]
+pub enum DeletedAction {
+    /// Only list them
+    #[default]
+    Report,
+    /// Move them to the _archive directory of the collection
+    Archive,
+    /// Remove them from disk
+    Delete,
+}
+
+#[derive(Args, Debug)]
+pub struct CollectionArgs {
+    /// Label of the local collection
+    #[arg(long, short)]
+    pub collection: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// Label of the local collection
+    #[arg(long, short)]
+    pub collection: Option<String>,
+
+    /// Remove damaged files, so `rebuild` downloads them again
+    #[arg(long)]
+    pub requeue: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct DedupeArgs {
+    /// How the files are linked: hardlink, reflink or symlink, overrides the config
+    #[arg(long, value_name = "MODE")]
+    pub link: Option<LinkMode>,
+}
+
+#[derive(Args, Debug)]
+pub struct RenameArgs {
+    /// Label of the local collection
+    #[arg(long, short)]
+    pub collection: Option<String>,
+
+    /// uuid, id or a template with {id}, {ext}, {collection}, {resolution}, {first_tag}
+    /// and {uuid} placeholders, defaults to the naming of the collection in the config
+    #[arg(long, short)]
+    pub naming: Option<Naming>,
+}
+
+#[derive(Args, Debug)]
+pub struct AddArgs {
+    /// Label of the local collection
+    #[arg(long, short)]
+    pub collection: Option<String>,
+
+    /// Url of the image
+    #[arg(long)]
+    pub url: Option<String>,
+
+    /// Tags of the image, separated by ,
+    #[arg(long, value_delimiter = ',')]
+    pub tags: Option<Vec<String>>,
+
+    /// File extension of the image ( example: jpg ), corrected to the format of the
+    /// downloaded content
+    #[arg(long)]
+    pub extension: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct ListArgs {
+    /// List collections of the Wallhaven user instead of local ones
+    #[arg(long, short)]
+    pub remote: bool,
+}
@@ -0,0 +1,747 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fmt::{self, Display},
+    fs,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    storage::{self, models::Metadata, LinkMode},
+    wallheaven::{self, models::SearchQuery},
+    webclient::RetryPolicy,
+};
+
+const CONFIG_PATH_KEY: &str = "WALLHEAVEN_SYNC_CONFIG";
+const STORAGE_PATH_KEY: &str = "WALLHEAVEN_SYNC_STORAGE_PATH";
+const API_KEY_KEY: &str = "WALLHEAVEN_API_KEY";
+const BASE_URL_KEY: &str = "WALLHEAVEN_SYNC_BASE_URL";
+
+/// Content of the configuration file. Every value is optional, missing ones
+/// are asked for interactively or fall back to defaults.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub username: Option<String>,
+    pub storage_path: Option<PathBuf>,
+    pub api_key: Option<ApiKey>,
+    /// File which contains only the API key, used when api_key is not set
+    pub api_key_file: Option<PathBuf>,
+    /// Number of wallpapers downloaded at the same time
+    pub jobs: Option<u16>,
+    /// Images larger than this are not downloaded, in bytes or with K, M or G suffix
+    pub max_size: Option<String>,
+    /// Wallpapers already stored in other collection are linked instead of downloaded
+    /// again: hardlink, reflink or symlink
+    pub dedupe: Option<LinkMode>,
+    /// Url of the Wallhaven API, for mirrors or local stand-ins
+    pub base_url: Option<String>,
+    /// Labels or ids of the collections synchronized when none is selected
+    pub collections: Vec<String>,
+    /// Labels or ids of the collections never synchronized
+    pub exclude_collections: Vec<String>,
+    /// Per collection overrides, keyed by collection label
+    pub collection: BTreeMap<String, CollectionConfig>,
+    /// Searches mirrored into local collections, keyed by collection label
+    pub search: BTreeMap<String, SearchQuery>,
+    pub retry: RetryPolicy,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollectionConfig {
+    /// Directory of the collection, relative paths are resolved against the storage path
+    pub directory: Option<String>,
+    pub naming: Naming,
+    pub filters: Filters,
+}
+
+/// How downloaded files are named, either `uuid`, `id` or a template like
+/// `{collection}-{id}-{resolution}.{ext}`
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Naming {
+    /// Random uuid, `<uuid>.<ext>`
+    #[default]
+    Uuid,
+    /// Wallhaven id of the wallpaper, `<id>.<ext>`
+    Id,
+    /// Placeholders in braces are replaced with values of the wallpaper
+    Template(String),
+}
+
+/// Placeholders which can be used in the filename template
+const PLACEHOLDERS: [&str; 6] = ["id", "ext", "collection", "resolution", "first_tag", "uuid"];
+
+/// Values of the wallpaper substituted into the filename template
+pub struct FilenameFields<'a> {
+    pub id: &'a str,
+    pub ext: &'a str,
+    pub collection: &'a str,
+    pub resolution: Option<&'a str>,
+    pub tags: &'a [String],
+}
+
+impl Naming {
+    fn template(&self) -> &str {
+        match self {
+            Naming::Uuid => "{uuid}.{ext}",
+            Naming::Id => "{id}.{ext}",
+            Naming::Template(template) => template,
+        }
+    }
+
+    /// Name of the file, characters which are unsafe in filenames are replaced with `_`
+    pub fn filename(&self, fields: &FilenameFields) -> String {
+        let mut filename = self.template().to_owned();
+
+        for placeholder in PLACEHOLDERS {
+            let key = format!("{{{}}}", placeholder);
+            if !filename.contains(&key) {
+                continue;
+            }
+
+            let value = match placeholder {
+                "id" => fields.id.to_owned(),
+                "ext" => fields.ext.to_owned(),
+                "collection" => fields.collection.to_owned(),
+                "resolution" => fields.resolution.unwrap_or("unknown").to_owned(),
+                "first_tag" => fields
+                    .tags
+                    .first()
+                    .map(String::as_str)
+                    .unwrap_or("untagged")
+                    .to_owned(),
+                _ => uuid::Uuid::new_v4().to_string(),
+            };
+
+            filename = filename.replace(&key, &sanitize(&value));
+        }
+
+        let filename = filename
+            .trim_start_matches('.')
+            .trim_end_matches(['.', ' ']);
+
+        match filename.is_empty() {
+            true => format!("{}.{}", sanitize(fields.id), sanitize(fields.ext)),
+            false => filename.to_owned(),
+        }
+    }
+}
+
+/// Replaces path separators, characters reserved on Windows, whitespace and control characters
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_whitespace() || c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// `label` when it is a single plain path component, otherwise its sanitized form,
+/// so `../x` or `a/b` can't point outside of the storage
+fn directory_name(label: &str) -> String {
+    let mut components = Path::new(label).components();
+    let plain = matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(name)), None) if name == label
+    ) && !label.chars().any(char::is_control);
+
+    if plain {
+        return label.to_owned();
+    }
+
+    match sanitize(label).trim_start_matches('.') {
+        "" => "_".to_owned(),
+        name => name.to_owned(),
+    }
+}
+
+impl TryFrom<String> for Naming {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        match value.as_str() {
+            "uuid" => return Ok(Naming::Uuid),
+            "id" => return Ok(Naming::Id),
+            _ => (),
+        }
+
+        if !value.contains("{") {
+            return Err(format!(
+                "'{}' is not a naming, expected uuid, id or a template like {{id}}.{{ext}}",
+                value
+            ));
+        }
+
+        if sanitize(&value) != value {
+            return Err(format!(
+                "template '{}' contains characters which are not allowed in filenames",
+                value
+            ));
+        }
+
+        let mut rest = value.as_str();
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed placeholder in template '{}'", value))?;
+            let placeholder = &rest[start + 1..start + end];
+
+            if !PLACEHOLDERS.contains(&placeholder) {
+                return Err(format!(
+                    "unknown placeholder {{{}}} in template '{}', expected one of {}",
+                    placeholder,
+                    value,
+                    PLACEHOLDERS.map(|e| format!("{{{}}}", e)).join(", ")
+                ));
+            }
+
+            rest = &rest[start + end + 1..];
+        }
+
+        Ok(Naming::Template(value))
+    }
+}
+
+impl FromStr for Naming {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        Naming::try_from(value.to_owned())
+    }
+}
+
+impl From<Naming> for String {
+    fn from(value: Naming) -> Self {
+        match value {
+            Naming::Uuid => "uuid".to_owned(),
+            Naming::Id => "id".to_owned(),
+            Naming::Template(template) => template,
+        }
+    }
+}
+
+impl Display for Naming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from(self.clone()))
+    }
+}
+
+/// Rules which wallpapers have to pass to be downloaded. Wallpapers whose value
+/// is not known, such as the ones added by url, pass the rule.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Filters {
+    /// Wallpaper must have at least one of these tags
+    pub include_tags: Vec<String>,
+    /// Wallpaper can't have any of these tags
+    pub exclude_tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_height: Option<u32>,
+    /// Allowed aspect ratios, for example `16x9` or `1.6`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ratios: Vec<String>,
+    /// Allowed purities: sfw, sketchy or nsfw
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub purity: Vec<String>,
+    /// Allowed categories: general, anime or people
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
+    /// Larger images are skipped, in bytes or with K, M or G suffix
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<String>,
+}
+
+/// Difference between aspect ratios which are still considered the same
+const RATIO_TOLERANCE: f64 = 0.01;
+
+impl Filters {
+    /// Checks values which can't be checked by serde
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if let Some(size) = &self.max_file_size {
+            parse_size(size).map_err(|err| format!("invalid max_file_size: {}", err))?;
+        }
+
+        match self.ratios.iter().find(|e| parse_ratio(e).is_none()) {
+            Some(ratio) => Err(format!(
+                "invalid ratio '{}', expected for example 16x9 or 1.6",
+                ratio
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns reason of rejection, or None when wallpaper passes the filters
+    pub fn rejects(&self, wallpaper: &Metadata) -> Option<String> {
+        self.rejects_properties(wallpaper)
+            .or_else(|| self.rejects_tags(&wallpaper.tags))
+    }
+
+    /// Checks every rule but the tags, which are missing from listings of the API
+    pub fn rejects_properties(&self, wallpaper: &Metadata) -> Option<String> {
+        let allows = |allowed: &[String], value: &Option<String>| match value {
+            Some(value) => {
+                allowed.is_empty() || allowed.iter().any(|e| e.eq_ignore_ascii_case(value))
+            }
+            None => true,
+        };
+
+        if let (Some(minimum), Some(width)) = (self.min_width, wallpaper.dimension_x) {
+            if width < minimum {
+                return Some(format!("width {} is below {}", width, minimum));
+            }
+        }
+
+        if let (Some(minimum), Some(height)) = (self.min_height, wallpaper.dimension_y) {
+            if height < minimum {
+                return Some(format!("height {} is below {}", height, minimum));
+            }
+        }
+
+        if let Some(ratio) = wallpaper_ratio(wallpaper) {
+            let allowed = self
+                .ratios
+                .iter()
+                .filter_map(|e| parse_ratio(e))
+                .any(|e| (e - ratio).abs() < RATIO_TOLERANCE);
+
+            if !self.ratios.is_empty() && !allowed {
+                return Some(format!("ratio {:.2} is not allowed", ratio));
+            }
+        }
+
+        if !allows(&self.purity, &wallpaper.purity) {
+            return Some(format!(
+                "purity {} is not allowed",
+                wallpaper.purity.as_deref().unwrap_or_default()
+            ));
+        }
+
+        if !allows(&self.categories, &wallpaper.category) {
+            return Some(format!(
+                "category {} is not allowed",
+                wallpaper.category.as_deref().unwrap_or_default()
+            ));
+        }
+
+        let limit = self
+            .max_file_size
+            .as_deref()
+            .and_then(|e| parse_size(e).ok());
+        if let (Some(limit), Some(size)) = (limit, wallpaper.file_size) {
+            if size > limit {
+                return Some(format!("size {} is over {} bytes", size, limit));
+            }
+        }
+
+        None
+    }
+
+    /// Returns reason of rejection by the tag rules
+    pub fn rejects_tags(&self, tags: &[String]) -> Option<String> {
+        let has_tag = |tag: &String| tags.iter().any(|e| e.eq_ignore_ascii_case(tag));
+
+        if let Some(tag) = self.exclude_tags.iter().find(|e| has_tag(e)) {
+            return Some(format!("excluded tag '{}'", tag));
+        }
+
+        if !self.include_tags.is_empty() && !self.include_tags.iter().any(has_tag) {
+            return Some("none of the included tags".to_owned());
+        }
+
+        None
+    }
+}
+
+/// Ratio written as `16x9`, `16:9` or `1.78`
+fn parse_ratio(value: &str) -> Option<f64> {
+    let ratio = match value.split_once(['x', ':']) {
+        Some((width, height)) => {
+            width.trim().parse::<f64>().ok()? / height.trim().parse::<f64>().ok()?
+        }
+        None => value.trim().parse().ok()?,
+    };
+
+    (ratio.is_finite() && ratio > 0.0).then_some(ratio)
+}
+
+/// Aspect ratio of the wallpaper from its dimensions, or the ratio reported by Wallhaven
+fn wallpaper_ratio(wallpaper: &Metadata) -> Option<f64> {
+    match (wallpaper.dimension_x, wallpaper.dimension_y) {
+        (Some(width), Some(height)) if height > 0 => Some(width as f64 / height as f64),
+        _ => wallpaper.ratio.as_deref().and_then(parse_ratio),
+    }
+}
+
+/// Where the value of a setting came from
+#[derive(Debug, Clone, Copy)]
+pub enum Source {
+    Default,
+    Environment,
+    ConfigFile,
+    KeyFile,
+    Flag,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self {
+            Source::Default => "default",
+            Source::Environment => "environment",
+            Source::ConfigFile => "config file",
+            Source::KeyFile => "api key file",
+            Source::Flag => "command line",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+/// Wallhaven API key, debug output shows `<redacted>` instead of the key
+#[derive(Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(transparent)]
+pub struct ApiKey(String);
+
+impl fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+/// Returns the first present candidate
+fn pick<T, const N: usize>(candidates: [(Option<T>, Source); N]) -> Option<Setting<T>> {
+    candidates
+        .into_iter()
+        .find_map(|(value, source)| value.map(|value| Setting { value, source }))
+}
+
+/// Configuration after merging command line flags, environment and config file
+#[derive(Debug)]
+pub struct Settings {
+    pub config_path: Setting<PathBuf>,
+    pub storage_path: Setting<PathBuf>,
+    pub username: Option<Setting<String>>,
+    pub api_key: Option<Setting<ApiKey>>,
+    pub jobs: Setting<usize>,
+    pub base_url: Setting<Url>,
+    pub max_size: Option<Setting<u64>>,
+    pub dedupe: Option<Setting<LinkMode>>,
+    pub collections: Vec<String>,
+    pub exclude_collections: Vec<String>,
+    pub overrides: BTreeMap<String, CollectionConfig>,
+    pub searches: BTreeMap<String, SearchQuery>,
+    pub retry: RetryPolicy,
+}
+
+impl Settings {
+    pub fn resolve(
+        config_flag: Option<PathBuf>,
+        storage_flag: Option<PathBuf>,
+        username_flag: Option<String>,
+        jobs_flag: Option<u16>,
+        max_size_flag: Option<u64>,
+    ) -> Result<Settings> {
+        let config_path = pick([
+            (config_flag, Source::Flag),
+            (
+                env::var(CONFIG_PATH_KEY).ok().map(PathBuf::from),
+                Source::Environment,
+            ),
+        ]);
+
+        let config_path = match config_path {
+            Some(value) => value,
+            None => Setting {
+                value: default_config_path()?,
+                source: Source::Default,
+            },
+        };
+
+        let config = load(&config_path.value)?;
+
+        let storage_path = pick([
+            (storage_flag, Source::Flag),
+            (
+                env::var(STORAGE_PATH_KEY).ok().map(PathBuf::from),
+                Source::Environment,
+            ),
+            (config.storage_path, Source::ConfigFile),
+        ]);
+
+        let storage_path = match storage_path {
+            Some(value) => value,
+            None => Setting {
+                value: storage::get_storage_path()?,
+                source: Source::Default,
+            },
+        };
+
+        let base_url = pick([
+            (env::var(BASE_URL_KEY).ok(), Source::Environment),
+            (config.base_url, Source::ConfigFile),
+            (
+                Some(wallheaven::DEFAULT_BASE_URL.to_owned()),
+                Source::Default,
+            ),
+        ])
+        .expect("default base url is always present");
+
+        let base_url = Setting {
+            value: Url::parse(&base_url.value).map_err(|err| {
+                Error::Config(format!("Invalid base_url '{}': {}", base_url.value, err))
+            })?,
+            source: base_url.source,
+        };
+
+        let max_size = match config.max_size {
+            Some(value) => Some(
+                parse_size(&value)
+                    .map_err(|err| Error::Config(format!("Invalid max_size: {}", err)))?,
+            ),
+            None => None,
+        };
+
+        for (label, collection) in &config.collection {
+            collection.filters.validate().map_err(|err| {
+                Error::Config(format!("Filters of collection '{}': {}", label, err))
+            })?;
+        }
+
+        Ok(Settings {
+            config_path,
+            storage_path,
+            username: pick([
+                (username_flag, Source::Flag),
+                (config.username, Source::ConfigFile),
+            ]),
+            api_key: pick([
+                (env::var(API_KEY_KEY).ok().map(ApiKey), Source::Environment),
+                (config.api_key, Source::ConfigFile),
+                (read_api_key_file(config.api_key_file)?, Source::KeyFile),
+            ]),
+            jobs: pick([
+                (jobs_flag, Source::Flag),
+                (config.jobs.filter(|e| *e > 0), Source::ConfigFile),
+                (Some(1), Source::Default),
+            ])
+            .map(|e| Setting {
+                value: e.value as usize,
+                source: e.source,
+            })
+            .expect("default jobs is always present"),
+            base_url,
+            max_size: pick([
+                (max_size_flag, Source::Flag),
+                (max_size, Source::ConfigFile),
+            ]),
+            dedupe: pick([(config.dedupe, Source::ConfigFile)]),
+            collections: config.collections,
+            exclude_collections: config.exclude_collections,
+            overrides: config.collection,
+            searches: config.search,
+            retry: config.retry,
+        })
+    }
+
+    pub fn api_key(&self) -> Option<&str> {
+        self.api_key.as_ref().map(|e| e.value.0.as_str())
+    }
+
+    pub fn collection(&self, label: &str) -> CollectionConfig {
+        self.overrides.get(label).cloned().unwrap_or_default()
+    }
+
+    /// Overrides of the collection stored in the local `directory`
+    pub fn local_collection(&self, directory: &str) -> CollectionConfig {
+        self.overrides
+            .keys()
+            .find(|label| self.collection_directory(label) == directory)
+            .map(|label| self.collection(label))
+            .unwrap_or_default()
+    }
+
+    /// Directory of the collection, relative to the storage path. Labels come from
+    /// Wallhaven, so the ones which aren't a plain name are sanitized to stay inside it.
+    pub fn collection_directory(&self, label: &str) -> String {
+        match self.overrides.get(label).and_then(|e| e.directory.clone()) {
+            Some(directory) => directory,
+            None => directory_name(label),
+        }
+    }
+}
+
+impl Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line =
+            |f: &mut fmt::Formatter<'_>, name: &str, value: Option<(String, Source)>| match value {
+                Some((value, source)) => writeln!(f, "{:<14}{} ({})", name, value, source),
+                None => writeln!(f, "{:<14}<unset>", name),
+            };
+
+        let config_path = &self.config_path;
+        let storage_path = &self.storage_path;
+
+        line(
+            f,
+            "config",
+            Some((config_path.value.display().to_string(), config_path.source)),
+        )?;
+        line(
+            f,
+            "storage_path",
+            Some((
+                storage_path.value.display().to_string(),
+                storage_path.source,
+            )),
+        )?;
+        line(
+            f,
+            "username",
+            self.username.as_ref().map(|e| (e.value.clone(), e.source)),
+        )?;
+        line(
+            f,
+            "api_key",
+            self.api_key
+                .as_ref()
+                .map(|e| ("<redacted>".to_owned(), e.source)),
+        )?;
+        line(
+            f,
+            "jobs",
+            Some((self.jobs.value.to_string(), self.jobs.source)),
+        )?;
+        line(
+            f,
+            "base_url",
+            Some((self.base_url.value.to_string(), self.base_url.source)),
+        )?;
+        line(
+            f,
+            "max_size",
+            self.max_size
+                .as_ref()
+                .map(|e| (e.value.to_string(), e.source)),
+        )?;
+        line(
+            f,
+            "dedupe",
+            self.dedupe
+                .as_ref()
+                .map(|e| (format!("{:?}", e.value).to_lowercase(), e.source)),
+        )?;
+        writeln!(f, "{:<14}{:?}", "collections", self.collections)?;
+        writeln!(f, "{:<14}{:?}", "exclude", self.exclude_collections)?;
+
+        writeln!(f, "{:<14}{:?}", "retry", self.retry)?;
+
+        for (label, collection) in &self.overrides {
+            writeln!(f, "[collection.{}]", label)?;
+            if let Some(directory) = &collection.directory {
+                writeln!(f, "  {:<14}{}", "directory", directory)?;
+            }
+            writeln!(f, "  {:<14}{}", "naming", collection.naming)?;
+            if let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(&collection.filters)
+            {
+                for (name, value) in fields {
+                    writeln!(f, "  {:<14}{}", name, value)?;
+                }
+            }
+        }
+
+        for (label, query) in &self.searches {
+            writeln!(f, "[search.{}]", label)?;
+            if let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(query) {
+                for (name, value) in fields {
+                    match value.as_str() {
+                        Some(value) => writeln!(f, "  {:<14}{}", name, value)?,
+                        None => writeln!(f, "  {:<14}{}", name, value)?,
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn read_api_key_file(path: Option<PathBuf>) -> Result<Option<ApiKey>> {
+    match path {
+        Some(path) => fs::read_to_string(&path)
+            .map(|e| Some(ApiKey(e.trim().to_owned())))
+            .map_err(Error::io(format!(
+                "Failed to read API key file '{}'",
+                path.display()
+            ))),
+        None => Ok(None),
+    }
+}
+
+/// Parses size in bytes, optionally with K, M or G suffix which are powers of 1024
+pub fn parse_size(value: &str) -> std::result::Result<u64, String> {
+    let value = value.trim();
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((index, 'k' | 'K')) => (&value[..index], 1 << 10),
+        Some((index, 'm' | 'M')) => (&value[..index], 1 << 20),
+        Some((index, 'g' | 'G')) => (&value[..index], 1 << 30),
+        _ => (value, 1),
+    };
+
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|e| e.checked_mul(multiplier))
+        .ok_or_else(|| {
+            format!(
+                "'{}' is not a size, expected for example 512K or 30M",
+                value
+            )
+        })
+}
+
+pub fn default_config_path() -> Result<PathBuf> {
+    let config_home = match env::var("XDG_CONFIG_HOME") {
+        Ok(value) if !value.is_empty() => PathBuf::from(value),
+        _ => env::home_dir()
+            .ok_or_else(|| Error::Config("Failed to determine home directory".to_owned()))?
+            .join(".config"),
+    };
+
+    Ok(config_home.join("wallheaven_sync").join("config.toml"))
+}
+
+/// Reads the config file, missing file results in default config.
+/// Files with `.json` extension are parsed as JSON, everything else as TOML.
+pub fn load(path: &Path) -> Result<Config> {
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+
+    let content = fs::read_to_string(path).map_err(Error::io(format!(
+        "Failed to read config '{}'",
+        path.display()
+    )))?;
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::from_str(&content).map_err(|err| err.to_string()),
+        _ => toml::from_str(&content).map_err(|err| err.to_string()),
+    }
+    .map_err(|err| Error::Config(format!("Failed to parse '{}': {}", path.display(), err)))
+}
@@ -0,0 +1,131 @@
+use std::{io, path::PathBuf};
+
+use reqwest::StatusCode;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
+    #[error("Server responded with {status} to request for {path}")]
+    HttpStatus { status: StatusCode, path: String },
+
+    #[error("Request for {path} failed: {source}")]
+    Transport {
+        path: String,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    #[error("Failed to parse {context}: {source}")]
+    Json {
+        context: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("Storage directory '{}' doesn't exist", .0.display())]
+    MissingStorage(PathBuf),
+
+    #[error("index.json of collection '{collection}' is corrupt: {source}")]
+    CorruptIndex {
+        collection: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("index.json of collection '{collection}' has schema version {version}, which is newer than this version of wallheaven_sync supports")]
+    UnsupportedIndex { collection: String, version: u64 },
+
+    #[error("Wallhaven rejected the API key")]
+    Unauthorized,
+
+    #[error("Wallhaven API error: {0}")]
+    Api(String),
+
+    #[error("Image {path} is larger than the limit of {limit} bytes")]
+    TooLarge { path: String, limit: u64 },
+
+    #[error("Response for {path} is not an image (Content-Type: {content_type})")]
+    NotImage { path: String, content_type: String },
+
+    #[error("No answer to '{0}', standard input is closed")]
+    NoInput(String),
+
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error("{0}")]
+    InvalidArgument(String),
+}
+
+impl Error {
+    pub fn io(context: impl Into<String>) -> impl FnOnce(io::Error) -> Error {
+        let context = context.into();
+        move |source| Error::Io { context, source }
+    }
+
+    pub fn json(context: impl Into<String>) -> impl FnOnce(serde_json::Error) -> Error {
+        let context = context.into();
+        move |source| Error::Json { context, source }
+    }
+
+    /// Transport error without the url, which could contain credentials
+    pub fn transport(path: &str) -> impl FnOnce(reqwest::Error) -> Error + '_ {
+        move |source| Error::Transport {
+            path: path.to_owned(),
+            source: source.without_url(),
+        }
+    }
+
+    /// Exit code of the process which failed with this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => 2,
+            Error::MissingStorage(_) => 3,
+            Error::CorruptIndex { .. } => 4,
+            Error::Io { .. } => 5,
+            Error::Transport { .. } => 6,
+            Error::HttpStatus { .. } => 7,
+            Error::Unauthorized => 8,
+            Error::Api(_) => 9,
+            Error::Json { .. } => 10,
+            Error::TooLarge { .. } => 11,
+            Error::UnsupportedIndex { .. } => 12,
+            Error::NotImage { .. } => 13,
+            Error::NoInput(_) => 14,
+            Error::NotFound(_) => 15,
+            Error::InvalidArgument(_) => 16,
+        }
+    }
+
+    /// Suggestion printed below the error message
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            Error::Config(_) => Some("Check the config file, `wallheaven_sync config` prints it"),
+            Error::MissingStorage(_) => {
+                Some("Run `wallheaven_sync sync` to create it or pass --storage")
+            }
+            Error::CorruptIndex { .. } => {
+                Some("Fix or remove index.json of the collection and run sync again")
+            }
+            Error::Transport { .. } => Some("Check the network connection and try again"),
+            Error::Unauthorized => Some("Check the API key in the Wallhaven account settings"),
+            Error::TooLarge { .. } => Some("Raise the limit with --max-size"),
+            Error::UnsupportedIndex { .. } => Some("Update wallheaven_sync"),
+            Error::NoInput(_) => {
+                Some("Pass the value with a flag, e.g. --collection, --username or --yes")
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,16 @@
+//! Synchronization of Wallhaven collections with a local storage directory.
+//!
+//! [`wallheaven::WallhavenClient`] talks to the Wallhaven API, [`storage::Storage`] manages
+//! the collection directories and their index.json and [`sync::Syncer`] downloads
+//! the difference between them.
+
+pub mod config;
+pub mod error;
+pub mod storage;
+pub mod sync;
+pub mod wallheaven;
+pub mod webclient;
+
+mod pool;
+
+pub use error::{Error, Result};
@@ -1,155 +1,256 @@
-use std::{env, fs, path::PathBuf, process::exit};
+use std::{collections::BTreeMap, fs, process::exit};
 
+use clap::{Parser, ValueEnum};
+use cli::{
+    AddArgs, Cli, CollectionArgs, Command, DedupeArgs, DeletedAction, FetchArgs, ListArgs,
+    RenameArgs, SearchArgs, SyncArgs, VerifyArgs,
+};
 use reqwest::Url;
-use storage::models::Metadata;
-use uuid::Uuid;
-use wallheaven::models::Wallpaper;
+use wallheaven_sync::{
+    config::Settings,
+    storage::{
+        models::{Index, Metadata},
+        ImageFormat, Problem, Storage,
+    },
+    sync::{Event, Removal, SyncPlan, SyncSummary, Syncer},
+    wallheaven::{
+        link::Link,
+        models::{Collection, SearchQuery},
+        WallhavenClient,
+    },
+    webclient, Error,
+};
 
-//TODO: Clean code below, Add some proper handling to errors
-//TODO: The methods which send request in wallheaven modules should go to webclient module
 //TODO: Change webclient module name to something better
 //
+mod cli;
 mod prompts;
-mod storage;
-mod wallheaven;
-mod webclient;
 fn main() {
-    let args: Vec<String> = env::args().collect();
-
-    match args.len() {
-        1 => sync(),
-        2 => match args[1].as_str() {
-            "--refresh" => refresh(),
-            "--rebuild" => rebuild(),
-            "--add" => add(),
-            "--help" => help(),
-            _ => sync(),
-        },
-        _ => help(),
+    let cli = Cli::parse();
+
+    let settings = Settings::resolve(
+        cli.config,
+        cli.storage,
+        cli.username,
+        cli.jobs,
+        cli.max_size,
+    )
+    .unwrap_or_else(|err| fail(err));
+
+    webclient::set_notifier(prompts::info);
+
+    match cli.command {
+        Some(Command::Sync(args)) => sync(&settings, args, cli.yes),
+        Some(Command::Rebuild(args)) => rebuild(&syncer(&settings), args),
+        Some(Command::Prune(args)) => prune(&syncer(&settings), args, cli.yes),
+        Some(Command::Add(args)) => add(&settings, args),
+        Some(Command::Verify(args)) => verify(&syncer(&settings), args),
+        Some(Command::Dedupe(args)) => dedupe(&settings, args),
+        Some(Command::Rename(args)) => rename(&settings, args, cli.yes),
+        Some(Command::Search(args)) => search(&settings, args, cli.yes),
+        Some(Command::Fetch(args)) => fetch(&settings, args, cli.yes),
+        Some(Command::List(args)) => list(&settings, args),
+        Some(Command::Config) => print!("{}", settings),
+        None => sync(&settings, SyncArgs::default(), cli.yes),
     };
 }
 
-fn help() {
-    println!(
-        "\
-Usage: wallheaven_sync [OPTION] 
-       wallheaven_sync [USERNAME] 
-
-Options: 
---refresh      Do not use
---rebuild      Download all wallpapers declared in index.json
---add          Add new wallpaper to index.json
---help         Print help menu"
+/// Prints the error with a hint and exits with the code of the error
+fn fail(err: Error) -> ! {
+    eprintln!("Error: {}", err);
+    if let Some(hint) = err.hint() {
+        eprintln!("{}", hint);
+    }
+    exit(err.exit_code());
+}
+
+fn syncer(settings: &Settings) -> Syncer {
+    let client = WallhavenClient::new(settings.api_key())
+        .unwrap_or_else(|err| fail(err))
+        .with_base_url(settings.base_url.value.clone())
+        .with_max_size(settings.max_size.as_ref().map(|e| e.value))
+        .with_retry_policy(settings.retry.clone());
+    Syncer::new(
+        client,
+        Storage::new(&settings.storage_path.value),
+        settings.jobs.value,
     )
+    .with_dedupe(settings.dedupe.as_ref().map(|e| e.value))
 }
 
-fn add() {
-    let storage_path = storage::get_storage_path();
+fn get_username(settings: &Settings) -> String {
+    match &settings.username {
+        Some(value) => value.value.clone(),
+        None => prompts::get_input_string("Username").unwrap_or_else(|err| fail(err)),
+    }
+}
 
-    if !storage_path.exists() {
-        println!("Storage directory is missing");
-        exit(1);
+fn confirm(prompt: &str, yes: bool) -> bool {
+    yes || prompts::get_input(prompt, prompts::mappers::bool_mapper_with_default(true))
+        .unwrap_or_else(|err| fail(err))
+}
+
+fn select_local_collection<'a>(collections: &'a [String], label: &Option<String>) -> &'a str {
+    match label {
+        Some(label) => match collections.iter().find(|e| e.eq(&label)) {
+            Some(value) => value,
+            None => fail(Error::NotFound(format!(
+                "Collection '{}' doesn't exist in storage",
+                label
+            ))),
+        },
+        None => prompts::select_from_list("Collections", collections, |e| e)
+            .unwrap_or_else(|err| fail(err))
+            .as_str(),
     }
+}
+
+fn find_remote_collection<'a>(collections: &'a [Collection], value: &str) -> &'a Collection {
+    match collections.iter().find(|e| matches_collection(e, value)) {
+        Some(value) => value,
+        None => fail(Error::NotFound(format!(
+            "Collection '{}' doesn't exist",
+            value
+        ))),
+    }
+}
+
+fn matches_collection(collection: &Collection, value: &str) -> bool {
+    collection.label.eq(value) || collection.id.to_string().eq(value)
+}
+
+/// Collections selected with flags, then the ones listed in config, otherwise asks the user.
+/// Excluded collections are removed from every selection.
+fn select_remote_collections<'a>(
+    collections: &'a [Collection],
+    args: &SyncArgs,
+    settings: &Settings,
+) -> Vec<&'a Collection> {
+    let selected = if args.all {
+        collections.iter().collect()
+    } else if !args.collection.is_empty() {
+        args.collection
+            .iter()
+            .map(|e| find_remote_collection(collections, e))
+            .collect()
+    } else if !settings.collections.is_empty() {
+        settings
+            .collections
+            .iter()
+            .map(|e| find_remote_collection(collections, e))
+            .collect()
+    } else {
+        vec![
+            prompts::select_from_list("Collections:", collections, |e| &e.label)
+                .unwrap_or_else(|err| fail(err)),
+        ]
+    };
+
+    selected
+        .into_iter()
+        .filter(|collection| {
+            !args
+                .exclude
+                .iter()
+                .chain(&settings.exclude_collections)
+                .any(|e| matches_collection(collection, e))
+        })
+        .collect()
+}
 
-    let collections = storage::get_collections(&storage_path);
+fn local_collections(storage: &Storage) -> Vec<String> {
+    let collections = storage.collections().unwrap_or_else(|err| fail(err));
 
     if collections.is_empty() {
         prompts::info("There are no collections in storage");
         exit(0)
     }
 
-    let selection = prompts::select_from_list("Collections", &collections, |e| &e);
-
-    let mut collection = storage::get_collection(&storage_path, selection).unwrap();
-    let url = get_url();
+    collections
+}
 
-    if collection.iter().any(|e| e.image_url.eq(&url.to_string())) {
-        prompts::info("Url already exists in storage metadata, aborting");
-        exit(1);
-    };
+fn list(settings: &Settings, args: ListArgs) {
+    if args.remote {
+        let collections = syncer(settings)
+            .client()
+            .collections(&get_username(settings))
+            .unwrap_or_else(|err| fail(err));
 
-    let tags = get_tags();
-    let extension = get_extension(&url);
-    let filename = format!("{}.{}", Uuid::new_v4().to_string(), extension);
+        for e in collections {
+            println!("{}\t{}\t{}", e.id, e.label, e.count);
+        }
+    } else {
+        for e in local_collections(&Storage::new(&settings.storage_path.value)) {
+            println!("{}", e);
+        }
+    }
+}
 
-    let metadata = Metadata {
-        filename,
-        tags,
-        source_url: url.to_string(),
-        image_url: url.to_string(),
+fn add(settings: &Settings, args: AddArgs) {
+    let syncer = &syncer(settings);
+    let collections = local_collections(syncer.storage());
+    let selection = select_local_collection(&collections, &args.collection);
+
+    let url = match args.url {
+        Some(value) => match Url::parse(&value) {
+            Ok(value) => value,
+            Err(err) => fail(Error::InvalidArgument(format!(
+                "Invalid url '{}': {}",
+                value, err
+            ))),
+        },
+        None => get_url(),
     };
 
-    match webclient::download_image(&url) {
-        Ok(value) => {
-            storage::save(&storage_path, selection, &metadata.filename, value);
-            prompts::info(&format!("Saved {}", &metadata.filename));
-            collection.push(metadata);
-            storage::persist_metadata(collection, selection, &storage_path);
-        }
-        Err(_) => {
-            println!("Failed to download image");
+    let tags = args.tags.unwrap_or_else(get_tags);
+    let extension = args.extension.unwrap_or_else(|| get_extension(&url));
+    let naming = &settings.local_collection(selection).naming;
+
+    match syncer.add_image(&url, selection, naming, tags, &extension) {
+        Ok(Some(metadata)) => prompts::info(&format!("Saved {}", metadata.filename)),
+        Ok(None) => {
+            prompts::info("Url already exists in storage metadata, aborting");
             exit(1);
         }
+        Err(err) => fail(err),
     }
 }
 
 fn get_url() -> Url {
-    let url = prompts::get_input_string("Url");
-    match Url::parse(&url) {
-        Ok(value) => value,
-        Err(_) => {
-            println!("Invalid value, please try again");
-            get_url()
+    loop {
+        let url = prompts::get_input_string("Url").unwrap_or_else(|err| fail(err));
+        match Url::parse(&url) {
+            Ok(value) => return value,
+            Err(_) => println!("Invalid value, please try again"),
         }
     }
 }
 
 fn get_tags() -> Vec<String> {
-    let value = prompts::get_input_string("Provide tags ( separated by , )");
+    let value = prompts::get_input_string("Provide tags ( separated by , )")
+        .unwrap_or_else(|err| fail(err));
 
     value
-        .split(",")
+        .split(',')
         .map(|e| e.to_owned())
         .collect::<Vec<String>>()
 }
 
+/// Extension of the image the url points to, it is only a guess which is corrected
+/// to the format of the content once downloaded
 fn get_extension(url: &Url) -> String {
-    match url.to_string().split(".").last() {
-        Some(value) if !value.is_empty() => {
-            let answer = prompts::get_input_string(&format!(
-                "Filetype {}, input to override or leave blank to confirm",
-                value
-            ));
-            if answer.is_empty() {
-                value.to_owned()
-            } else {
-                answer
-            }
-        }
-        _ => prompts::get_input_string("Input file extension ( example: jpg )"),
-    }
+    ImageFormat::from_url(url.path())
+        .map_or("jpg", ImageFormat::extension)
+        .to_owned()
 }
 
-fn rebuild() {
-    let storage_path = storage::get_storage_path();
-
-    if !storage_path.exists() {
-        println!("Cannot refresh not existing collection");
-        exit(1);
-    }
-
-    let collections = storage::get_collections(&storage_path);
+fn rebuild(syncer: &Syncer, args: CollectionArgs) {
+    let collections = local_collections(syncer.storage());
+    let selection = select_local_collection(&collections, &args.collection);
 
-    if collections.is_empty() {
-        prompts::info("There are no collections in storage");
-        exit(0)
-    }
+    let collection = syncer.storage().collection(selection);
 
-    let selection = prompts::select_from_list("Collections", &collections, |e| &e);
-
-    let collection_path = storage_path.join(selection);
-
-    let wallpapers = match storage::get_collection(&storage_path, selection) {
+    let mut wallpapers = match collection.load().unwrap_or_else(|err| fail(err)) {
         Some(value) if !value.is_empty() => value,
         Some(_) => {
             prompts::info("Collection is empty");
@@ -161,53 +262,146 @@ fn rebuild() {
         }
     };
 
-    let files = read_filenames_from_directory(&collection_path);
+    let to_rebuild = collection
+        .missing(&wallpapers)
+        .unwrap_or_else(|err| fail(err));
 
-    let to_rebuild = wallpapers
+    let resumable = to_rebuild
         .iter()
-        .filter(|e| !files.iter().any(|f| f.eq(&e.filename)))
-        .collect::<Vec<&Metadata>>();
+        .map(|e| e.image_url.as_str())
+        .collect::<Vec<&str>>();
+    let partial = collection
+        .remove_partial(&resumable)
+        .unwrap_or_else(|err| fail(err));
+    prompts::info_print("Removed partially written files", &partial, |e| e);
 
     prompts::info_print("Wallpapers to redownload", &to_rebuild, |e| &e.filename);
     prompts::info("Downloading:");
 
-    let to_rebuild_size = to_rebuild.len();
+    syncer
+        .rebuild(&collection, &mut wallpapers, print_event)
+        .unwrap_or_else(|err| fail(err));
+}
+
+fn verify(syncer: &Syncer, args: VerifyArgs) {
+    let collections = local_collections(syncer.storage());
+    let selection = select_local_collection(&collections, &args.collection);
 
-    for (index, e) in to_rebuild.iter().enumerate() {
-        prompts::print_progress(index + 1, to_rebuild_size, &e.filename);
-        let response = match webclient::download_image(&Url::parse(&e.image_url).unwrap()) {
-            Ok(response) => response,
-            Err(err) => {
-                println!("Failed to download image: {}", err);
-                continue;
-            }
-        };
+    let collection = syncer.storage().collection(selection);
+
+    let wallpapers = match collection.load().unwrap_or_else(|err| fail(err)) {
+        Some(value) => value,
+        None => {
+            println!("Nothing to verify");
+            exit(0)
+        }
+    };
 
-        let file_path = collection_path.join(&e.filename);
+    let damaged = syncer.verify(&collection, &wallpapers);
 
-        if let Err(err) = fs::write(file_path, response) {
-            println!("Failed to write to file {}", err);
+    let lines = damaged
+        .iter()
+        .map(|(e, problem)| format!("{} ({})", e.filename, problem))
+        .collect::<Vec<String>>();
+    prompts::info_print("Damaged files", &lines, |e| e);
+    prompts::info(&format!(
+        "{} of {} files are damaged",
+        damaged.len(),
+        wallpapers.len()
+    ));
+
+    if damaged.is_empty() {
+        return;
+    }
+
+    if !args.requeue {
+        exit(1);
+    }
+
+    for (e, problem) in &damaged {
+        if *problem != Problem::Missing {
+            collection
+                .remove(&e.filename)
+                .unwrap_or_else(|err| fail(err));
         }
     }
+
+    prompts::info("Run `wallheaven_sync rebuild` to download them again");
 }
 
-fn refresh() {
-    let storage_path = storage::get_storage_path();
+fn dedupe(settings: &Settings, args: DedupeArgs) {
+    let mode = args
+        .link
+        .or(settings.dedupe.as_ref().map(|e| e.value))
+        .unwrap_or_default();
 
-    if !storage_path.exists() {
-        println!("Cannot refresh not existing collection");
-        exit(1);
+    let duplicates = syncer(settings)
+        .dedupe(mode)
+        .unwrap_or_else(|err| fail(err));
+
+    let lines = duplicates
+        .iter()
+        .map(|e| format!("{}/{} -> {}", e.collection, e.filename, e.source.display()))
+        .collect::<Vec<String>>();
+    prompts::info_print("Linked files", &lines, |e| e);
+
+    let saved: u64 = duplicates.iter().map(|e| e.size).sum();
+    prompts::info(&format!(
+        "Linked {} files, saved {} bytes",
+        duplicates.len(),
+        saved
+    ));
+}
+
+fn rename(settings: &Settings, args: RenameArgs, yes: bool) {
+    let syncer = &syncer(settings);
+    let collections = local_collections(syncer.storage());
+    let selection = select_local_collection(&collections, &args.collection);
+
+    let collection = syncer.storage().collection(selection);
+    let naming = args
+        .naming
+        .unwrap_or_else(|| settings.local_collection(selection).naming);
+
+    let wallpapers = match collection.load().unwrap_or_else(|err| fail(err)) {
+        Some(value) => value,
+        None => {
+            println!("Nothing to rename");
+            exit(0)
+        }
+    };
+
+    let renames = syncer.renames(&collection, &wallpapers, &naming);
+
+    if renames.is_empty() {
+        prompts::info("Every file already follows the naming");
+        return;
     }
 
-    let collections = storage::get_collections(&storage_path);
+    let lines = renames
+        .iter()
+        .map(|e| format!("{} -> {}", e.from, e.to))
+        .collect::<Vec<String>>();
+    prompts::info_print("Files to rename", &lines, |e| e);
 
-    if collections.is_empty() {
-        prompts::info("There are no collections in storage");
-        exit(0)
+    if !confirm("Do you want to continue?[Y/n]", yes) {
+        println!("Aborting");
+        exit(0);
     }
 
-    let selection = prompts::select_from_list("Collections", &collections, |e| &e);
-    let wallpapers = match storage::get_collection(&storage_path, selection) {
+    collection
+        .rename_all(&renames)
+        .unwrap_or_else(|err| fail(err));
+    prompts::info(&format!("Renamed {} files", renames.len()));
+}
+
+fn prune(syncer: &Syncer, args: CollectionArgs, yes: bool) {
+    let collections = local_collections(syncer.storage());
+    let selection = select_local_collection(&collections, &args.collection);
+
+    let collection = syncer.storage().collection(selection);
+
+    let wallpapers = match collection.load().unwrap_or_else(|err| fail(err)) {
         Some(value) => value,
         None => {
             println!("Nothing to refresh");
@@ -215,17 +409,22 @@ fn refresh() {
         }
     };
 
-    let collection_path = storage_path.join(selection);
-    let files = read_filenames_from_directory(&collection_path);
+    let files = collection.filenames().unwrap_or_else(|err| fail(err));
 
-    for file in &files {
-        match wallpapers.iter().any(|e| e.filename.eq(file)) {
-            false => {
-                let _ = fs::remove_file(collection_path.join(&file));
-                ()
-            }
-            true => (),
-        };
+    let to_remove = files
+        .iter()
+        .filter(|file| !wallpapers.iter().any(|e| e.filename.eq(*file)))
+        .collect::<Vec<&String>>();
+
+    prompts::info_print("Files to remove", &to_remove, |e| e);
+
+    if !to_remove.is_empty() && !confirm("Do you want to continue?[Y/n]", yes) {
+        println!("Aborting");
+        exit(0);
+    }
+
+    for file in to_remove {
+        let _ = fs::remove_file(collection.path().join(file));
     }
 
     let updated_metadata: Vec<Metadata> = wallpapers
@@ -233,138 +432,356 @@ fn refresh() {
         .filter(|e| files.contains(&e.filename))
         .collect();
 
-    storage::persist_metadata(updated_metadata, selection, &storage_path);
+    collection
+        .persist(&updated_metadata)
+        .unwrap_or_else(|err| fail(err));
 }
 
-fn sync() {
-    let username = &prompts::get_input_string("Username");
+fn sync(settings: &Settings, args: SyncArgs, yes: bool) {
+    let syncer = syncer(settings);
+    let storage = syncer.storage();
+
+    ensure_storage(storage, yes);
+
+    let saved = saved_searches(settings, storage);
+    let searches = match args.all {
+        true => saved.into_iter().collect(),
+        false => args
+            .search
+            .iter()
+            .map(|label| match saved.get(label) {
+                Some(query) => (label.clone(), query.clone()),
+                None => fail(Error::NotFound(format!("Search '{}' is not saved", label))),
+            })
+            .collect::<Vec<(String, SearchQuery)>>(),
+    };
 
-    let storage_path = storage::get_storage_path();
+    let mut summaries = vec![];
 
-    println!(
-        "Storage path: {}",
-        &storage_path
-            .to_str()
-            .expect("Failed to convert path to str")
-    );
+    if args.search.is_empty() || !args.collection.is_empty() {
+        let username = &get_username(settings);
+        let collections = syncer
+            .client()
+            .collections(username)
+            .unwrap_or_else(|err| fail(err));
 
-    if !storage_path.exists() {
-        match prompts::get_input(
-            "Storage doesn't exists, do You want to create it?[Y/n]",
-            prompts::mappers::bool_mapper_with_default(true),
-        ) {
-            true => storage::init_storage(&storage_path),
-            false => {
-                println!("Aborting");
+        if collections.is_empty() {
+            println!("There are no collections for user: {}", username);
+            if searches.is_empty() {
                 exit(0)
             }
+        } else {
+            summaries.extend(
+                select_remote_collections(&collections, &args, settings)
+                    .into_iter()
+                    .map(|selected_collection| {
+                        sync_collection(
+                            &syncer,
+                            settings,
+                            username,
+                            selected_collection,
+                            args.deleted,
+                            yes,
+                        )
+                    }),
+            );
         }
     }
 
-    let collections = wallheaven::get_collections(&username);
-
-    if collections.is_empty() {
-        println!("There are no collections for user: {}", username);
-        exit(0)
+    for (label, query) in &searches {
+        summaries.push(sync_search(
+            &syncer,
+            settings,
+            label,
+            query,
+            args.deleted,
+            yes,
+        ));
     }
 
-    let selected_collection = prompts::select_from_list("Collections:", &collections, |e| &e.label);
+    print_summary(&summaries);
+}
+
+fn search(settings: &Settings, args: SearchArgs, yes: bool) {
+    let syncer = syncer(settings);
+
+    let criteria = SearchQuery {
+        q: args.query,
+        uploader: args.uploader,
+        categories: args.categories,
+        purity: args.purity,
+        atleast: args.atleast,
+        ratios: args.ratios,
+        colors: args.colors,
+        sorting: args.sorting,
+        top_range: args.top_range,
+        ..Default::default()
+    };
+
+    // Limits alone refresh the saved search, any other flag starts a new one
+    let query = match criteria == SearchQuery::default() {
+        true => match saved_searches(settings, syncer.storage()).remove(&args.name) {
+            Some(saved) => SearchQuery {
+                max_pages: args.max_pages.or(saved.max_pages),
+                max_wallpapers: args.max_wallpapers.or(saved.max_wallpapers),
+                ..saved
+            },
+            None => fail(Error::NotFound(format!(
+                "Search '{}' is not saved, pass the query",
+                args.name
+            ))),
+        },
+        false => SearchQuery {
+            max_pages: args.max_pages,
+            max_wallpapers: args.max_wallpapers,
+            ..criteria
+        },
+    };
+
+    ensure_storage(syncer.storage(), yes);
 
-    prompts::synchronization_info(selected_collection);
+    let summary = sync_search(&syncer, settings, &args.name, &query, args.deleted, yes);
+    print_summary(&[summary]);
+}
+
+fn fetch(settings: &Settings, args: FetchArgs, yes: bool) {
+    let syncer = syncer(settings);
+
+    ensure_storage(syncer.storage(), yes);
 
-    let wallpapers_in_collection =
-        wallheaven::get_wallpapers_from_collection(&username, selected_collection.id);
+    let label = |default: Option<&str>| match (&args.collection, default) {
+        (Some(value), _) => value.clone(),
+        (None, Some(value)) => value.to_owned(),
+        (None, None) => prompts::get_input_string("Label of the local collection")
+            .unwrap_or_else(|err| fail(err)),
+    };
 
-    let collection_from_storage =
-        match storage::get_collection(&storage_path, &selected_collection.label) {
-            Some(val) => val,
-            None => {
-                storage::init_collection(&storage_path, &selected_collection.label);
-                vec![]
+    let summary = match args.link {
+        Link::Search(query) => {
+            let query = SearchQuery {
+                max_pages: args.max_pages,
+                max_wallpapers: args.max_wallpapers,
+                ..*query
+            };
+            let label = label(query.uploader.as_deref());
+            sync_search(&syncer, settings, &label, &query, args.deleted, yes)
+        }
+        Link::Collection { username, id } => {
+            let collections = syncer
+                .client()
+                .collections(&username)
+                .unwrap_or_else(|err| fail(err));
+            let remote = find_remote_collection(&collections, &id.to_string());
+            let local = Collection {
+                id: remote.id,
+                label: label(Some(&remote.label)),
+                count: remote.count,
+            };
+            sync_collection(&syncer, settings, &username, &local, args.deleted, yes)
+        }
+        Link::Wallpaper(id) => {
+            let label = label(None);
+            let directory = settings.collection_directory(&label);
+
+            match syncer.add(&id, &directory, &settings.collection(&label)) {
+                Ok(Some(metadata)) => prompts::info(&format!("Saved {}", metadata.filename)),
+                Ok(None) => prompts::info("Wallpaper is already in the collection"),
+                Err(err) => fail(err),
             }
-        };
+            return;
+        }
+    };
 
-    let not_synced = find_not_synced(&wallpapers_in_collection, &collection_from_storage);
+    print_summary(&[summary]);
+}
 
-    if not_synced.is_empty() {
-        prompts::info("Everything is up to date");
-        exit(0);
+/// Searches of the config and the ones saved in index.json of local collections
+/// by the `search` command, keyed by label. The config takes precedence.
+fn saved_searches(settings: &Settings, storage: &Storage) -> BTreeMap<String, SearchQuery> {
+    let mut searches = settings.searches.clone();
+
+    for directory in storage.collections().unwrap_or_default() {
+        if searches
+            .keys()
+            .any(|label| settings.collection_directory(label) == directory)
+        {
+            continue;
+        }
+
+        if let Ok(Some(Index {
+            search: Some(query),
+            ..
+        })) = storage.collection(&directory).load_index()
+        {
+            searches.insert(directory, query);
+        }
     }
 
-    prompts::info_print("Wallpapers to sync", &not_synced, |e| &e.url);
+    searches
+}
 
-    let confirm_sync = prompts::get_input(
-        "Do you want to continue?[Y/n]",
-        prompts::mappers::bool_mapper_with_default(true),
-    );
+fn ensure_storage(storage: &Storage, yes: bool) {
+    println!("Storage path: {}", storage.path().display());
 
-    if !confirm_sync {
-        println!("Aborting");
-        exit(0);
+    if !storage.exists() {
+        match confirm(
+            "Storage doesn't exists, do You want to create it?[Y/n]",
+            yes,
+        ) {
+            true => storage.init().unwrap_or_else(|err| fail(err)),
+            false => {
+                println!("Aborting");
+                exit(0)
+            }
+        }
     }
+}
 
-    let mut new_metadata = vec![];
+fn print_summary(summaries: &[SyncSummary]) {
+    println!("\nSummary:");
+    println!(
+        "{:<30}{:>8}{:>8}{:>8}{:>8}",
+        "Collection", "New", "Skipped", "Removed", "Failed"
+    );
+    for e in summaries {
+        println!(
+            "{:<30}{:>8}{:>8}{:>8}{:>8}",
+            e.label,
+            e.new,
+            e.skipped,
+            e.removed,
+            e.failed.len()
+        );
+    }
 
-    println!("Downloading:");
+    let filtered = summaries
+        .iter()
+        .flat_map(|e| e.filtered.iter())
+        .collect::<Vec<&String>>();
+    prompts::info_print("Skipped by filters", &filtered, |e| e);
 
-    let not_synced_len = not_synced.len();
-
-    for (index, e) in not_synced.iter().enumerate() {
-        prompts::print_progress(index + 1, not_synced_len, &e.url);
-        let file_metadata = wallheaven::download_wallpaper_metadata(&e);
-        //TODO split this. wallheaven module should download the file bytes and the storage module
-        //should save it into hard drive
-        wallheaven::save_image_content(
-            &file_metadata.image_url,
-            &storage_path,
-            &selected_collection.label,
-            &file_metadata.filename,
-        );
+    let failed = summaries
+        .iter()
+        .flat_map(|e| e.failed.iter())
+        .collect::<Vec<&String>>();
+    prompts::info_print("Failed", &failed, |e| e);
+}
 
-        new_metadata.push(file_metadata);
+fn print_event(event: Event) {
+    match event {
+        Event::Started { index, total, name } => prompts::print_progress(index, total, name),
+        Event::Linked { name, source } => {
+            prompts::info(&format!("Linked {} to {}", name, source.display()))
+        }
+        Event::Skipped { name, reason } => prompts::info(&format!("Skipping {}, {}", name, reason)),
+        Event::Failed { name, error } => println!("Failed to download {}: {}", name, error),
     }
+}
 
-    let updated_collection: Vec<Metadata> = collection_from_storage
-        .into_iter()
-        .chain(new_metadata.into_iter())
-        .collect();
+fn sync_collection(
+    syncer: &Syncer,
+    settings: &Settings,
+    username: &str,
+    selected_collection: &Collection,
+    deleted: DeletedAction,
+    yes: bool,
+) -> SyncSummary {
+    let label = &selected_collection.label;
+    prompts::synchronization_info("collection", label);
+
+    let directory = &settings.collection_directory(label);
+    let plan = syncer.plan(username, selected_collection, directory);
+
+    sync_plan(syncer, settings, label, plan, deleted, yes)
+}
 
-    storage::persist_metadata(
-        updated_collection,
-        &selected_collection.label,
-        &storage_path,
-    )
+fn sync_search(
+    syncer: &Syncer,
+    settings: &Settings,
+    label: &str,
+    query: &SearchQuery,
+    deleted: DeletedAction,
+    yes: bool,
+) -> SyncSummary {
+    prompts::synchronization_info("search", label);
+
+    let directory = &settings.collection_directory(label);
+    let plan = syncer.plan_search(query, directory);
+
+    sync_plan(syncer, settings, label, plan, deleted, yes)
 }
 
-fn find_not_synced<'a>(
-    from_wallheaven: &'a Vec<Wallpaper>,
-    from_collection: &Vec<Metadata>,
-) -> Vec<&'a Wallpaper> {
-    let mut not_synced: Vec<&Wallpaper> = vec![];
-    for x in from_wallheaven {
-        let mut contains = false;
-        for y in from_collection {
-            if *x.url == *y.source_url {
-                contains = true;
-                break;
+/// Removes, reports and downloads the difference found by `plan`
+fn sync_plan(
+    syncer: &Syncer,
+    settings: &Settings,
+    label: &str,
+    plan: wallheaven_sync::Result<SyncPlan>,
+    deleted: DeletedAction,
+    yes: bool,
+) -> SyncSummary {
+    let collection_config = settings.collection(label);
+
+    let mut summary = SyncSummary::new(label);
+
+    let mut plan = match plan {
+        Ok(value) => value,
+        Err(err) => {
+            println!("Failed to synchronize collection: {}", err);
+            summary.fail(label, &err);
+            return summary;
+        }
+    };
+    prompts::info_print("Removed partially written files", &plan.partial, |e| e);
+
+    let removed = plan.removed();
+
+    if !removed.is_empty() {
+        prompts::info_print("Wallpapers removed from Wallhaven", &removed, |e| {
+            &e.source_url
+        });
+
+        let removal = match deleted {
+            DeletedAction::Report => None,
+            DeletedAction::Archive => Some(Removal::Archive),
+            DeletedAction::Delete => Some(Removal::Delete),
+        };
+
+        if let Some(removal) = removal {
+            if confirm(
+                &format!(
+                    "Do you want to {} them?[Y/n]",
+                    deleted.to_possible_value().unwrap().get_name()
+                ),
+                yes,
+            ) {
+                syncer.remove(&mut plan, removal, &mut summary);
             }
         }
-        if !contains {
-            not_synced.push(x);
+    }
+
+    let not_synced = plan.not_synced();
+    summary.skipped = plan.remote.len() - not_synced.len();
+
+    if not_synced.is_empty() {
+        prompts::info("Everything is up to date");
+        if let Err(err) = plan.mark_synced() {
+            summary.fail(label, &err);
         }
+        return summary;
     }
 
-    not_synced
-}
+    prompts::info_print("Wallpapers to sync", &not_synced, |e| &e.url);
 
-fn read_filenames_from_directory(path: &PathBuf) -> Vec<String> {
-    fs::read_dir(path)
-        .unwrap()
-        .into_iter()
-        .filter(|e| e.is_ok())
-        .map(|e| e.unwrap())
-        .map(|e| e.file_name())
-        .map(|e| e.to_str().unwrap().to_owned())
-        .filter(|e| !(*e).eq("index.json"))
-        .collect()
+    if !confirm("Do you want to continue?[Y/n]", yes) {
+        println!("Aborting");
+        summary.skipped += not_synced.len();
+        return summary;
+    }
+
+    println!("Downloading:");
+
+    syncer.download(plan, &collection_config, &mut summary, print_event);
+
+    summary
 }
@@ -1,11 +1,3 @@
-pub fn bool_mapper(input: &str) -> Option<bool> {
-    match input {
-        "y" | "Y" => Some(true),
-        "n" | "N" => Some(false),
-        _ => None,
-    }
-}
-
 pub fn bool_mapper_with_default(default: bool) -> impl Fn(&str) -> Option<bool> {
     move |input| match input {
         "y" | "Y" => Some(true),
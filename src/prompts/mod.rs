@@ -3,12 +3,12 @@ use std::{
     str::FromStr,
 };
 
-use crate::wallheaven::models::Collection;
+use wallheaven_sync::{Error, Result};
 
 pub mod mappers;
 pub mod validations;
 
-pub fn select_from_list<'a, F, T>(header: &str, entries: &'a Vec<T>, map: F) -> &'a T
+pub fn select_from_list<'a, F, T>(header: &str, entries: &'a [T], map: F) -> Result<&'a T>
 where
     F: Fn(&T) -> &str,
 {
@@ -26,33 +26,25 @@ where
         "Select option",
         mappers::i32_mapper,
         validations::in_range(1, entries.len() as i32),
-    ) - 1;
+    )? - 1;
 
-    entries
+    Ok(entries
         .get(selection as usize)
-        .expect("get_input_i32 guarantes value in range")
+        .expect("get_input_i32 guarantes value in range"))
 }
 
-pub fn synchronization_info(collection: &Collection) {
-    println!("Synchronizing collection: {}", collection.label)
+pub fn synchronization_info(kind: &str, label: &str) {
+    println!("Synchronizing {}: {}", kind, label)
 }
 
-pub fn get_input<T>(prompt: &str, mapper: impl Fn(&str) -> Option<T>) -> T
+pub fn get_input<T>(prompt: &str, mapper: impl Fn(&str) -> Option<T>) -> Result<T>
 where
     T: FromStr,
 {
-    print!("{}: ", prompt);
-    stdout().flush().expect("Failed to flush stdout!");
-    let mut input = "".to_owned();
-    io::stdin()
-        .read_line(&mut input)
-        .expect("Failed to read user input");
-
-    match mapper(input.trim()) {
-        Some(value) => value,
-        None => {
-            println!("Incorrect value! Please ");
-            get_input(prompt, mapper)
+    loop {
+        match mapper(&get_input_string(prompt)?) {
+            Some(value) => return Ok(value),
+            None => println!("Incorrect value! Please try again"),
         }
     }
 }
@@ -61,18 +53,17 @@ pub fn get_input_with_validation<T>(
     prompt: &str,
     mapper: impl Fn(&str) -> Option<T>,
     validation: impl Fn(&T) -> Option<String>,
-) -> T
+) -> Result<T>
 where
     T: FromStr,
 {
-    let value = get_input(prompt, &mapper);
+    loop {
+        let value = get_input(prompt, &mapper)?;
 
-    match validation(&value) {
-        Some(error) => {
-            println!("{}", error);
-            get_input_with_validation(prompt, mapper, validation)
+        match validation(&value) {
+            Some(error) => println!("{}", error),
+            None => return Ok(value),
         }
-        None => value,
     }
 }
 
@@ -83,12 +74,11 @@ where
     let body = new_metadata
         .iter()
         .enumerate()
-        .map(|(index, e)| format!("{} -> {}\n", index + 1, mapper(&e)))
+        .map(|(index, e)| format!("{} -> {}\n", index + 1, mapper(e)))
         .reduce(|acc, e| acc + &e);
 
-    match body {
-        Some(value) => println!("{}:\n{}", header, value),
-        None => (),
+    if let Some(value) = body {
+        println!("{}:\n{}", header, value)
     }
 }
 
@@ -100,13 +90,18 @@ pub(crate) fn print_progress(index: usize, total: usize, body: &str) {
     println!("[{}/{}] {}...", index, total, body);
 }
 
-pub fn get_input_string(prompt: &str) -> String {
+/// Reads a line from stdin, fails once it is closed instead of asking again or
+/// picking a default, so unattended runs never answer on behalf of the user
+pub fn get_input_string(prompt: &str) -> Result<String> {
     print!("{}: ", prompt);
     stdout().flush().expect("Failed to flush stdout!");
     let mut input = "".to_owned();
-    io::stdin()
+    let read = io::stdin()
         .read_line(&mut input)
-        .expect("Failed to read user input");
+        .map_err(Error::io("Failed to read user input"))?;
 
-    input.trim().to_owned()
+    match read {
+        0 => Err(Error::NoInput(prompt.to_owned())),
+        _ => Ok(input.trim().to_owned()),
+    }
 }
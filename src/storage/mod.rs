@@ -1,154 +1,721 @@
-use core::panic;
 use std::{
     env,
-    ffi::OsStr,
+    fmt::{self, Display},
     fs::{self, read_dir, File},
-    io::Write,
+    io::{self, ErrorKind, Write},
     path::{Path, PathBuf},
     str::FromStr,
 };
 
-use self::models::Metadata;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+
+use self::models::{Index, Metadata, Rename, SCHEMA_VERSION};
 
 pub mod models;
 
-const ALLOWED_WALLPAPER_FORMATS: [&'static str; 3] = ["jpg", "png", "jpeg"];
+pub const ARCHIVE_DIRECTORY: &str = "_archive";
 
-pub fn save(storage_path: &PathBuf, collection: &str, filename: &str, content: Vec<u8>) {
-    let file_path = storage_path.join(collection).join(filename);
-    if let Err(err) = fs::write(&file_path, content) {
-        println!(
-            "Failed to save file under path '{}', reason: {}",
-            file_path.to_str().unwrap(),
-            err
-        )
-    };
+const INDEX_FILE: &str = "index.json";
+
+/// Suffix of files which are being written, they are renamed once complete
+const TEMP_SUFFIX: &str = ".tmp";
+
+/// Suffix of unfinished downloads, they are kept to be resumed by the next run
+const PART_SUFFIX: &str = ".part";
+
+/// Renames in progress, see `Collection::rename_all`
+const RENAME_JOURNAL: &str = "index.json.renames";
+
+/// Suffix of files moved aside by an unfinished rename, unlike partial files they are kept
+const STAGED_SUFFIX: &str = ".renaming";
+
+/// Content of the rename journal
+#[derive(Serialize, Deserialize)]
+struct RenameJournal {
+    renames: Vec<Rename>,
+    /// Every file was moved to its staged name
+    staged: bool,
+}
+
+pub fn get_storage_path() -> Result<PathBuf> {
+    //TODO write some better way to determine default path for windows
+    match env::home_dir() {
+        Some(home) => Ok(home.join("wallheaven_storage")),
+        None => Err(Error::Config(
+            "Failed to determine home directory, storage path must be set".to_owned(),
+        )),
+    }
 }
 
-pub fn get_storage_path() -> PathBuf {
-    let key = "WALLHEAVEN_SYNC_STORAGE_PATH";
+/// Reason why file of the wallpaper can't be trusted
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    Missing,
+    Empty,
+    Size {
+        expected: u64,
+        actual: u64,
+    },
+    Checksum,
+    /// File exists, but it can't be read
+    Unreadable(String),
+}
 
-    let value = match env::var(key) {
-        Ok(value) => value,
-        Err(_) => {
-            //TODO write some better way to determine default path for windows
-            let home = env::home_dir().unwrap().to_str().unwrap().to_owned();
-            home + "/wallheaven_storage"
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Missing => write!(f, "missing"),
+            Problem::Empty => write!(f, "empty file"),
+            Problem::Size { expected, actual } => {
+                write!(f, "{} bytes, expected {}", actual, expected)
+            }
+            Problem::Checksum => write!(f, "checksum mismatch"),
+            Problem::Unreadable(err) => write!(f, "{}", err),
         }
-    };
+    }
+}
 
-    PathBuf::from_str(&value).unwrap()
+/// How identical images in the storage share their content
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkMode {
+    /// Files are hard links to the same data, removing one keeps the others
+    #[default]
+    Hardlink,
+    /// Copy on write clone, needs a file system which supports it like Btrfs or XFS
+    Reflink,
+    /// Symbolic link to the first file, removing that file breaks the links
+    Symlink,
 }
 
-pub fn init_storage(storage_path: &PathBuf) {
-    if let Err(err) = fs::create_dir(storage_path) {
-        panic!("Failed to create storage directory: {}", err)
+impl FromStr for LinkMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "hardlink" => Ok(LinkMode::Hardlink),
+            "reflink" => Ok(LinkMode::Reflink),
+            "symlink" => Ok(LinkMode::Symlink),
+            _ => Err(format!(
+                "'{}' is not a link mode, expected hardlink, reflink or symlink",
+                value
+            )),
+        }
     }
 }
 
-pub fn init_collection(storage_path: &PathBuf, label: &str) {
-    let collection_path = storage_path.join(label);
+/// Format of a wallpaper, detected from the content of the file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Webp,
+    Gif,
+    Avif,
+}
+
+impl ImageFormat {
+    /// Number of bytes from the start of the file `sniff` needs
+    pub const SNIFF_LENGTH: usize = 12;
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Png => "png",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Gif => "gif",
+            ImageFormat::Avif => "avif",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<ImageFormat> {
+        match extension.to_ascii_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+            "png" => Some(ImageFormat::Png),
+            "webp" => Some(ImageFormat::Webp),
+            "gif" => Some(ImageFormat::Gif),
+            "avif" => Some(ImageFormat::Avif),
+            _ => None,
+        }
+    }
+
+    /// Format of the file the url points to, judging by its extension.
+    /// Query and fragment of the url are ignored.
+    pub fn from_url(url: &str) -> Option<ImageFormat> {
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+        let name = path.rsplit('/').next().unwrap_or(path);
+
+        ImageFormat::from_extension(name.rsplit_once('.')?.1)
+    }
+
+    /// Format of `Content-Type` header value, None when it isn't a known image type
+    pub fn from_content_type(value: &str) -> Option<ImageFormat> {
+        let mime = value.split(';').next().unwrap_or(value).trim();
+
+        match mime.to_ascii_lowercase().as_str() {
+            "image/jpeg" | "image/jpg" | "image/pjpeg" => Some(ImageFormat::Jpeg),
+            "image/png" => Some(ImageFormat::Png),
+            "image/webp" => Some(ImageFormat::Webp),
+            "image/gif" => Some(ImageFormat::Gif),
+            "image/avif" => Some(ImageFormat::Avif),
+            _ => None,
+        }
+    }
 
-    if let Err(_) = fs::metadata(&collection_path) {
-        match fs::create_dir(&collection_path) {
-            Ok(val) => val,
-            Err(err) => {
-                panic!("Failed to create collection directory: {}", err)
+    /// Format of the content starting with `head`, judging by its magic bytes
+    pub fn sniff(head: &[u8]) -> Option<ImageFormat> {
+        match head {
+            [0xFF, 0xD8, 0xFF, ..] => Some(ImageFormat::Jpeg),
+            [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some(ImageFormat::Png),
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(ImageFormat::Gif),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => {
+                Some(ImageFormat::Webp)
+            }
+            [_, _, _, _, b'f', b't', b'y', b'p', b'a', b'v', b'i', b'f' | b's', ..] => {
+                Some(ImageFormat::Avif)
+            }
+            _ => None,
+        }
+    }
+
+    /// `filename` with the extension of this format. An extension which already
+    /// stands for the format, such as `jpeg`, is kept.
+    pub fn filename(self, filename: &str) -> String {
+        let stem = match filename.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => {
+                if ImageFormat::from_extension(extension) == Some(self) {
+                    return filename.to_owned();
+                }
+                stem
             }
+            _ => filename,
         };
+
+        format!("{}.{}", stem, self.extension())
     }
 }
 
-pub fn get_collection(storage_path: &PathBuf, label: &str) -> Option<Vec<Metadata>> {
-    let collection_path = storage_path.join(label);
+/// Storage directory, every subdirectory is a collection
+#[derive(Debug, Clone)]
+pub struct Storage {
+    path: PathBuf,
+}
+
+impl Storage {
+    pub fn new(path: impl Into<PathBuf>) -> Storage {
+        Storage { path: path.into() }
+    }
 
-    if !Path::new(&collection_path).exists() {
-        return None;
+    pub fn path(&self) -> &Path {
+        &self.path
     }
 
-    let iterator = match read_dir(collection_path) {
-        Ok(iterator) => iterator,
-        Err(err) => {
-            panic!("Failed to read collection directory: {}", err);
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub fn init(&self) -> Result<()> {
+        fs::create_dir(&self.path).map_err(Error::io("Failed to create storage directory"))
+    }
+
+    /// Names of the collection directories
+    pub fn collections(&self) -> Result<Vec<String>> {
+        if !self.exists() {
+            return Err(Error::MissingStorage(self.path.clone()));
         }
-    };
 
-    let mut metadata_file = None;
-    let mut entries = vec![];
+        let dir_iterator =
+            fs::read_dir(&self.path).map_err(Error::io("Failed to read storage directory"))?;
 
-    for e in iterator {
-        let file = match e {
-            Ok(file) => file,
-            Err(err) => {
-                println!("Skipping entry: {}", err);
-                continue;
+        let mut collections = vec![];
+
+        for e in dir_iterator {
+            let e = e.map_err(Error::io("Failed to read directory"))?;
+            let metadata = e.metadata().map_err(Error::io("Failed to get metadata"))?;
+
+            if metadata.is_dir() {
+                collections.push(e.file_name().to_string_lossy().into_owned())
+            }
+        }
+
+        Ok(collections)
+    }
+
+    /// Every collection of the storage which has index.json, with its entries
+    pub fn indexes(&self) -> Result<Vec<(Collection, Vec<Metadata>)>> {
+        let mut indexes = vec![];
+
+        for name in self.collections()? {
+            let collection = self.collection(&name);
+            if let Some(entries) = collection.load()? {
+                indexes.push((collection, entries));
             }
+        }
+
+        Ok(indexes)
+    }
+
+    /// Collection stored in the directory, relative directories are resolved against the storage
+    pub fn collection(&self, directory: &str) -> Collection {
+        Collection {
+            name: directory.to_owned(),
+            path: self.path.join(directory),
+        }
+    }
+}
+
+/// Directory with wallpapers and index.json which describes them
+#[derive(Debug, Clone)]
+pub struct Collection {
+    name: String,
+    path: PathBuf,
+}
+
+impl Collection {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub fn init(&self) -> Result<()> {
+        if fs::metadata(&self.path).is_err() {
+            fs::create_dir(&self.path)
+                .map_err(Error::io("Failed to create collection directory"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Entries of index.json, None when the collection or its index doesn't exist
+    pub fn load(&self) -> Result<Option<Vec<Metadata>>> {
+        Ok(self.load_index()?.map(|index| index.wallpapers))
+    }
+
+    /// Content of index.json, None when the collection or its index doesn't exist.
+    /// Index of an older format is migrated and saved, the old file is kept as backup.
+    /// Renames interrupted by a crash are finished first.
+    pub fn load_index(&self) -> Result<Option<Index>> {
+        if !self.exists() {
+            return Ok(None);
+        }
+
+        self.finish_renames()?;
+        self.read_index()
+    }
+
+    fn read_index(&self) -> Result<Option<Index>> {
+        match fs::read_to_string(self.path.join(INDEX_FILE)) {
+            Ok(content) => self.parse_index(&content).map(Some),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Error::io("Failed to read content of index.json")(err)),
+        }
+    }
+
+    fn parse_index(&self, content: &str) -> Result<Index> {
+        let corrupt = |source| Error::CorruptIndex {
+            collection: self.name.clone(),
+            source,
+        };
+        let value: serde_json::Value = serde_json::from_str(content).map_err(corrupt)?;
+
+        let version = match &value {
+            serde_json::Value::Array(_) => 0,
+            value => value
+                .get("schema_version")
+                .and_then(serde_json::Value::as_u64)
+                .unwrap_or(0),
+        };
+
+        if version > SCHEMA_VERSION {
+            return Err(Error::UnsupportedIndex {
+                collection: self.name.clone(),
+                version,
+            });
+        }
+
+        let index = match version {
+            0 => Index::new(serde_json::from_value(value).map_err(corrupt)?),
+            _ => serde_json::from_value(value).map_err(corrupt)?,
         };
 
-        if file.path().is_dir() {
-            continue;
+        if version < SCHEMA_VERSION {
+            write_atomic(&self.path, &backup_name(version), content.as_bytes())?;
+            self.persist_index(&index)?;
         }
 
-        let filename = file.file_name();
-        let file_format = Path::new(&filename).extension().and_then(OsStr::to_str);
+        Ok(index)
+    }
+
+    /// Loads the index, missing collection is created
+    pub fn load_or_init(&self) -> Result<Vec<Metadata>> {
+        match self.load()? {
+            Some(value) => Ok(value),
+            None => self.init().map(|_| vec![]),
+        }
+    }
+
+    /// Saves the entries to index.json, keeping the rest of the index as it is
+    pub fn persist(&self, metadata: &[Metadata]) -> Result<()> {
+        let mut index = self.load_index()?.unwrap_or_else(|| Index::new(vec![]));
+        index.wallpapers = metadata.to_vec();
+
+        self.persist_index(&index)
+    }
+
+    pub fn persist_index(&self, index: &Index) -> Result<()> {
+        let json = serde_json::to_string(index).map_err(Error::json("index.json"))?;
+
+        write_atomic(&self.path, INDEX_FILE, json.as_bytes())
+    }
+
+    /// Removes files left behind by writes which were interrupted and unfinished downloads
+    /// of images other than `keep`, which can still be resumed. Returns names of removed files.
+    pub fn remove_partial(&self, keep: &[&str]) -> Result<Vec<String>> {
+        if !self.exists() {
+            return Ok(vec![]);
+        }
 
-        if "index.json" == filename {
-            metadata_file = Some(file)
-        } else if let Some(format) = file_format {
-            if ALLOWED_WALLPAPER_FORMATS.contains(&format) {
-                entries.push(file)
+        let mut removed = vec![];
+
+        for e in read_dir(&self.path).map_err(Error::io("Failed to read collection directory"))? {
+            let e = e.map_err(Error::io("Failed to read directory"))?;
+            let filename = e.file_name().to_string_lossy().into_owned();
+
+            let resumable = keep.iter().any(|url| part_name(url) == filename);
+
+            if is_partial(&filename) && !resumable && !e.path().is_dir() {
+                self.remove(&filename)?;
+                removed.push(filename);
             }
         }
+
+        Ok(removed)
+    }
+
+    /// Wallpapers of the index without a file on disk
+    pub fn missing<'a>(&self, wallpapers: &'a [Metadata]) -> Result<Vec<&'a Metadata>> {
+        let files = self.filenames()?;
+
+        Ok(wallpapers
+            .iter()
+            .filter(|e| !files.contains(&e.filename))
+            .collect())
+    }
+
+    /// Compares the file of the wallpaper with its size and checksum recorded in the index.
+    /// Wallpapers downloaded before checksums were recorded are only checked for presence.
+    pub fn check(&self, wallpaper: &Metadata) -> Result<Option<Problem>> {
+        let (size, sha256) = match self.hash(&wallpaper.filename)? {
+            Some(value) => value,
+            None => return Ok(Some(Problem::Missing)),
+        };
+
+        if size == 0 {
+            return Ok(Some(Problem::Empty));
+        }
+
+        if let Some(expected) = wallpaper.size.filter(|e| *e != size) {
+            return Ok(Some(Problem::Size {
+                expected,
+                actual: size,
+            }));
+        }
+
+        match &wallpaper.sha256 {
+            Some(expected) if *expected != sha256 => Ok(Some(Problem::Checksum)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Size and hex encoded SHA-256 of the file, None when it doesn't exist
+    pub fn hash(&self, filename: &str) -> Result<Option<(u64, String)>> {
+        let path = self.path.join(filename);
+        let context = || format!("Failed to read '{}'", path.display());
+
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(Error::io(context())(err)),
+        };
+
+        let mut hasher = Sha256::new();
+        let size = io::copy(&mut file, &mut hasher).map_err(Error::io(context()))?;
+
+        Ok(Some((size, format!("{:x}", hasher.finalize()))))
+    }
+
+    /// Names of the files in the collection directory, without the index
+    pub fn filenames(&self) -> Result<Vec<String>> {
+        Ok(fs::read_dir(&self.path)
+            .map_err(Error::io("Failed to read collection directory"))?
+            .map_while(std::result::Result::ok)
+            .filter(|e| !e.path().is_dir())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|e| !is_index(e) && !is_partial(e) && !is_staged(e))
+            .collect())
+    }
+
+    pub fn save(&self, filename: &str, content: &[u8]) -> Result<()> {
+        write_atomic(&self.path, filename, content)
+    }
+
+    /// File which holds unfinished download of the image
+    pub fn part_path(&self, image_url: &str) -> PathBuf {
+        self.path.join(part_name(image_url))
     }
 
-    match metadata_file {
-        Some(file) => match fs::read_to_string(file.path()) {
-            Ok(content) => match serde_json::from_str(&content) {
-                Ok(v) => v,
-                Err(err) => panic!("Failed to parse index.json. {}", err),
-            },
-            Err(err) => {
-                panic!("Failed to read content of index.json, {}", err);
+    /// Renames finished download of the image to `filename`
+    pub fn finish_part(&self, image_url: &str, filename: &str) -> Result<()> {
+        rename_durable(&self.path, &self.part_path(image_url), filename)
+    }
+
+    /// Replaces `filename` with a link to `source`, which is a file of this or other collection
+    pub fn link(&self, source: &Path, filename: &str, mode: LinkMode) -> Result<()> {
+        let temp_path = self.path.join(temp_name(filename));
+        let _ = fs::remove_file(&temp_path);
+
+        let linked = match mode {
+            LinkMode::Hardlink => fs::hard_link(source, &temp_path),
+            LinkMode::Reflink => reflink_copy::reflink(source, &temp_path),
+            LinkMode::Symlink => {
+                fs::canonicalize(source).and_then(|source| symlink(&source, &temp_path))
             }
-        },
-        None => None,
+        };
+
+        linked.map_err(Error::io(format!(
+            "Failed to link '{}' to '{}'",
+            self.path.join(filename).display(),
+            source.display()
+        )))?;
+
+        rename_durable(&self.path, &temp_path, filename)
+    }
+
+    /// Renames files of the wallpapers and their entries in index.json. Files are moved
+    /// to staged names first, so wallpapers can swap their names. The renames are written
+    /// to a journal before anything is moved, a rename interrupted at any point is
+    /// finished by the next `load_index`.
+    pub fn rename_all(&self, renames: &[Rename]) -> Result<()> {
+        let journal = RenameJournal {
+            renames: renames.to_vec(),
+            staged: false,
+        };
+
+        self.write_journal(&journal)?;
+        self.apply_renames(journal)
+    }
+
+    fn finish_renames(&self) -> Result<()> {
+        let content = match fs::read_to_string(self.path.join(RENAME_JOURNAL)) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(Error::io("Failed to read journal of renames")(err)),
+        };
+        let journal = serde_json::from_str(&content).map_err(Error::json("journal of renames"))?;
+
+        self.apply_renames(journal)
+    }
+
+    fn apply_renames(&self, mut journal: RenameJournal) -> Result<()> {
+        // Entries are found by source url, so renaming them again changes nothing
+        if let Some(mut index) = self.read_index()? {
+            for e in index.wallpapers.iter_mut() {
+                if let Some(rename) = journal
+                    .renames
+                    .iter()
+                    .find(|rename| rename.source_url == e.source_url)
+                {
+                    e.filename = rename.to.clone();
+                }
+            }
+            self.persist_index(&index)?;
+        }
+
+        // Until every file is staged, none of them has its new name yet
+        if !journal.staged {
+            for rename in &journal.renames {
+                let staged = staged_name(&rename.from);
+                if !self.path.join(&staged).exists() {
+                    self.rename(&rename.from, &staged)?;
+                }
+            }
+
+            journal.staged = true;
+            self.write_journal(&journal)?;
+        }
+
+        for rename in &journal.renames {
+            self.rename(&staged_name(&rename.from), &rename.to)?;
+        }
+
+        fs::remove_file(self.path.join(RENAME_JOURNAL))
+            .map_err(Error::io("Failed to remove journal of renames"))
+    }
+
+    fn write_journal(&self, journal: &RenameJournal) -> Result<()> {
+        let json = serde_json::to_string(journal).map_err(Error::json("journal of renames"))?;
+
+        write_atomic(&self.path, RENAME_JOURNAL, json.as_bytes())
+    }
+
+    /// Renames the file, missing file is not an error
+    pub fn rename(&self, from: &str, to: &str) -> Result<()> {
+        match fs::rename(self.path.join(from), self.path.join(to)) {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(Error::io(format!(
+                "Failed to rename '{}' to '{}'",
+                from, to
+            ))(err)),
+        }
+    }
+
+    /// Moves the file into the archive directory of the collection
+    pub fn archive(&self, filename: &str) -> Result<()> {
+        let archive_path = self.path.join(ARCHIVE_DIRECTORY);
+
+        if !self.path.join(filename).exists() {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&archive_path)
+            .map_err(Error::io("Failed to create archive directory"))?;
+
+        fs::rename(self.path.join(filename), archive_path.join(filename))
+            .map_err(Error::io(format!("Failed to archive '{}'", filename)))
+    }
+
+    pub fn remove(&self, filename: &str) -> Result<()> {
+        match fs::remove_file(self.path.join(filename)) {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(Error::io(format!("Failed to remove '{}'", filename))(err)),
+        }
     }
 }
 
-pub fn persist_metadata(updated_collection: Vec<Metadata>, label: &str, storage_path: &PathBuf) {
-    let full_path_to_metadata = storage_path.join(label).join("index.json");
-    let mut file = match File::create(full_path_to_metadata) {
-        Ok(file) => file,
-        Err(err) => {
-            panic!("{}", err)
+#[cfg(unix)]
+fn symlink(source: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(source, link)
+}
+
+#[cfg(windows)]
+fn symlink(source: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(source, link)
+}
+
+/// Whether both paths lead to the same data, through a symbolic or hard link
+pub fn is_same_file(first: &Path, second: &Path) -> bool {
+    if let (Ok(first), Ok(second)) = (fs::canonicalize(first), fs::canonicalize(second)) {
+        if first == second {
+            return true;
         }
-    };
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
 
-    let json = match serde_json::to_string(&updated_collection) {
-        Ok(value) => value,
-        Err(err) => panic!("{}", err),
+        if let (Ok(first), Ok(second)) = (fs::metadata(first), fs::metadata(second)) {
+            return first.dev() == second.dev() && first.ino() == second.ino();
+        }
+    }
+
+    false
+}
+
+/// index.json, backup of its older version or the rename journal
+fn is_index(filename: &str) -> bool {
+    filename == INDEX_FILE
+        || filename == RENAME_JOURNAL
+        || (filename.starts_with(INDEX_FILE) && filename.ends_with(".bak"))
+}
+
+/// Name of the backup of index.json in format `version`
+fn backup_name(version: u64) -> String {
+    format!("{}.v{}.bak", INDEX_FILE, version)
+}
+
+/// Name of `filename` while it is written, a leftover one is a partial file
+fn temp_name(filename: &str) -> String {
+    format!(".{}{}", filename, TEMP_SUFFIX)
+}
+
+/// Name of `filename` while the collection is renamed
+fn staged_name(filename: &str) -> String {
+    format!(".{}{}", filename, STAGED_SUFFIX)
+}
+
+fn is_staged(filename: &str) -> bool {
+    filename.starts_with('.') && filename.ends_with(STAGED_SUFFIX)
+}
+
+fn is_partial(filename: &str) -> bool {
+    filename.starts_with('.')
+        && (filename.ends_with(TEMP_SUFFIX) || filename.ends_with(PART_SUFFIX))
+}
+
+/// Unfinished downloads are named after the image, so they can be resumed
+/// even when the file gets a random name
+fn part_name(image_url: &str) -> String {
+    let name = image_url
+        .rsplit('/')
+        .next()
+        .unwrap_or(image_url)
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default();
+
+    format!(".{}{}", name, PART_SUFFIX)
+}
+
+/// Writes the content into a temporary file in the same directory, flushes it to disk
+/// and renames it to `filename`. After a crash the file either has the old content, the
+/// new content or a leftover temporary file exists, which `Collection::remove_partial` removes.
+fn write_atomic(directory: &Path, filename: &str, content: &[u8]) -> Result<()> {
+    let temp_path = directory.join(temp_name(filename));
+
+    let write = || -> std::io::Result<()> {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(content)?;
+        file.sync_all()
     };
 
-    let _ = file.write_all(json.as_bytes());
+    if let Err(err) = write() {
+        let _ = fs::remove_file(&temp_path);
+        return Err(Error::io(format!(
+            "Failed to write '{}'",
+            directory.join(filename).display()
+        ))(err));
+    }
+
+    rename_durable(directory, &temp_path, filename)
 }
 
-pub fn get_collections(storage_path: &PathBuf) -> Vec<String> {
-    let dir_iterator = fs::read_dir(storage_path).expect("Storage is missing");
+/// Renames complete file to `filename` and flushes the directory, so the rename survives a crash
+fn rename_durable(directory: &Path, from: &Path, filename: &str) -> Result<()> {
+    let path = directory.join(filename);
 
-    let mut collections = vec![];
+    fs::rename(from, &path).map_err(Error::io(format!(
+        "Failed to rename '{}' to '{}'",
+        from.display(),
+        path.display()
+    )))?;
 
-    for e in dir_iterator {
-        match e {
-            Ok(e) => match e.metadata() {
-                Ok(metadata) if metadata.is_dir() => {
-                    collections.push(e.file_name().to_str().unwrap().to_owned())
-                }
-                Ok(_) => (),
-                Err(err) => panic!("Failed to get metadata: {}", err),
-            },
-            Err(err) => panic!("Failed to read directory: {}", err),
-        };
+    #[cfg(unix)]
+    if let Ok(directory) = File::open(directory) {
+        let _ = directory.sync_all();
     }
 
-    collections
+    Ok(())
 }
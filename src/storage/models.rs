@@ -1,9 +1,99 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+use crate::wallheaven::models::SearchQuery;
+
+/// Version of the index.json format written by this version. Older formats are
+/// migrated when the index is loaded, the bare array of entries is version 0.
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// Content of index.json
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Index {
+    pub schema_version: u64,
+    /// Id of the Wallhaven collection the local one is synchronized with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collection_id: Option<i32>,
+    /// Owner of the Wallhaven collection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Search whose results the collection mirrors, instead of a Wallhaven collection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search: Option<SearchQuery>,
+    /// End of the last sync, as HTTP date
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_synced: Option<String>,
+    pub wallpapers: Vec<Metadata>,
+}
+
+impl Index {
+    pub fn new(wallpapers: Vec<Metadata>) -> Index {
+        Index {
+            schema_version: SCHEMA_VERSION,
+            collection_id: None,
+            username: None,
+            search: None,
+            last_synced: None,
+            wallpapers,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Metadata {
     pub filename: String,
     pub tags: Vec<String>,
     pub source_url: String,
     pub image_url: String,
+    /// Resolution of the image, for example `1920x1080`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimension_x: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimension_y: Option<u32>,
+    /// Aspect ratio as reported by Wallhaven, for example `1.78`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ratio: Option<String>,
+    /// Size of the image in bytes as reported by Wallhaven
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_size: Option<u64>,
+    /// MIME type of the image, for example `image/jpeg`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_type: Option<String>,
+    /// `sfw`, `sketchy` or `nsfw`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub purity: Option<String>,
+    /// `general`, `anime` or `people`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    /// Dominant colors of the image as hex codes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub colors: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub views: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub favorites: Option<u64>,
+    /// Username of the uploader
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uploader: Option<String>,
+    /// Original source of the image, if the uploader provided one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Upload time as reported by Wallhaven, for example `2018-10-31 01:23:10`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    /// Hex encoded SHA-256 of the file, recorded when it was downloaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Size of the file in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+/// New filename of the wallpaper with `source_url`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rename {
+    pub source_url: String,
+    pub from: String,
+    pub to: String,
 }
@@ -0,0 +1,847 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use reqwest::Url;
+
+use crate::{
+    config::{CollectionConfig, FilenameFields, Naming},
+    error::{Error, Result},
+    pool,
+    storage::{
+        self,
+        models::{Index, Metadata, Rename},
+        ImageFormat, LinkMode, Problem, Storage,
+    },
+    wallheaven::{
+        self,
+        models::{Collection, SearchQuery, Wallpaper},
+        WallhavenClient,
+    },
+};
+
+/// Outcome of synchronizing single collection
+#[derive(Debug, Default)]
+pub struct SyncSummary {
+    pub label: String,
+    pub new: usize,
+    pub skipped: usize,
+    pub removed: usize,
+    pub failed: Vec<String>,
+    /// Wallpapers rejected by the filters of the collection, with the reason
+    pub filtered: Vec<String>,
+}
+
+impl SyncSummary {
+    pub fn new(label: &str) -> SyncSummary {
+        SyncSummary {
+            label: label.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn fail(&mut self, name: &str, err: &Error) {
+        self.failed.push(format!("{} ({})", name, err));
+    }
+}
+
+/// Progress of the downloads, reported while they run
+pub enum Event<'a> {
+    /// Download of the `index`-th entry, starting from 1, has started
+    Started {
+        index: usize,
+        total: usize,
+        name: &'a str,
+    },
+    /// Wallpaper is already stored in other collection, the file was linked to it
+    Linked {
+        name: &'a str,
+        source: &'a Path,
+    },
+    /// Wallpaper was rejected by the filters of the collection
+    Skipped {
+        name: &'a str,
+        reason: &'a str,
+    },
+    Failed {
+        name: &'a str,
+        error: &'a Error,
+    },
+}
+
+/// What happens with local wallpapers removed from the Wallhaven collection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Removal {
+    /// Move them to the _archive directory of the collection
+    Archive,
+    /// Remove them from disk
+    Delete,
+}
+
+/// What the local collection mirrors
+#[derive(Debug, Clone)]
+pub enum Origin {
+    /// Wallhaven collection `id` of the user
+    Collection { username: String, id: i32 },
+    /// Results of the search
+    Search(Box<SearchQuery>),
+}
+
+/// Difference between the Wallhaven collection or search and the local collection
+pub struct SyncPlan {
+    pub collection: storage::Collection,
+    pub origin: Origin,
+    pub local: Vec<Metadata>,
+    pub remote: Vec<Wallpaper>,
+    /// Files of interrupted writes and downloads which were removed from the collection
+    pub partial: Vec<String>,
+}
+
+impl SyncPlan {
+    /// Wallpapers of the Wallhaven collection missing from index.json
+    pub fn not_synced(&self) -> Vec<&Wallpaper> {
+        find_not_synced(&self.remote, &self.local)
+    }
+
+    /// Local wallpapers which are not part of the Wallhaven collection anymore
+    pub fn removed(&self) -> Vec<&Metadata> {
+        find_removed(&self.remote, &self.local)
+    }
+
+    /// Records the sync in index.json when there is nothing to download
+    pub fn mark_synced(&self) -> Result<()> {
+        self.collection
+            .persist_index(&synced_index(&self.origin, self.local.clone()))
+    }
+}
+
+/// Result of synchronizing single wallpaper
+enum Outcome {
+    New,
+    Skipped(String),
+    Failed(String),
+}
+
+/// File of other collection which was linked to avoid storing the same image twice
+#[derive(Debug)]
+pub struct Duplicate {
+    pub collection: String,
+    pub filename: String,
+    pub source: PathBuf,
+    pub size: u64,
+}
+
+/// Diffs Wallhaven collections with the local ones and downloads the difference
+pub struct Syncer {
+    client: WallhavenClient,
+    storage: Storage,
+    jobs: usize,
+    dedupe: Option<LinkMode>,
+}
+
+impl Syncer {
+    pub fn new(client: WallhavenClient, storage: Storage, jobs: usize) -> Syncer {
+        Syncer {
+            client,
+            storage,
+            jobs,
+            dedupe: None,
+        }
+    }
+
+    /// Wallpapers already stored in other collections are linked with `mode` instead
+    /// of downloaded again
+    pub fn with_dedupe(mut self, mode: Option<LinkMode>) -> Syncer {
+        self.dedupe = mode;
+        self
+    }
+
+    pub fn client(&self) -> &WallhavenClient {
+        &self.client
+    }
+
+    pub fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    /// Fetches the Wallhaven collection and loads the local one stored in `directory`,
+    /// which is created when missing
+    pub fn plan(&self, username: &str, remote: &Collection, directory: &str) -> Result<SyncPlan> {
+        let origin = Origin::Collection {
+            username: username.to_owned(),
+            id: remote.id,
+        };
+        let remote = self.client.collection_wallpapers(username, remote.id)?;
+
+        self.plan_from(origin, remote, directory)
+    }
+
+    /// Runs the search and loads the local collection stored in `directory`,
+    /// which is created when missing
+    pub fn plan_search(&self, query: &SearchQuery, directory: &str) -> Result<SyncPlan> {
+        let remote = self.client.search(query)?;
+
+        self.plan_from(Origin::Search(Box::new(query.clone())), remote, directory)
+    }
+
+    fn plan_from(
+        &self,
+        origin: Origin,
+        remote: Vec<Wallpaper>,
+        directory: &str,
+    ) -> Result<SyncPlan> {
+        let collection = self.storage.collection(directory);
+        let local = collection.load_or_init()?;
+        let resumable = find_not_synced(&remote, &local)
+            .into_iter()
+            .map(|e| e.path.as_str())
+            .collect::<Vec<&str>>();
+        let partial = collection.remove_partial(&resumable)?;
+
+        Ok(SyncPlan {
+            collection,
+            origin,
+            local,
+            remote,
+            partial,
+        })
+    }
+
+    /// Downloads the Wallhaven wallpaper `id` into the local collection stored in `directory`
+    /// and adds it to index.json. Returns None when the collection already has it.
+    pub fn add(
+        &self,
+        id: &str,
+        directory: &str,
+        config: &CollectionConfig,
+    ) -> Result<Option<Metadata>> {
+        let collection = self.storage.collection(directory);
+        let mut local = collection.load_or_init()?;
+        let source_url = wallheaven::wallpaper_url(id);
+
+        if local.iter().any(|e| e.source_url == source_url) {
+            return Ok(None);
+        }
+
+        let wallpaper = Wallpaper {
+            id: id.to_owned(),
+            url: source_url,
+            ..Default::default()
+        };
+        let metadata =
+            self.client
+                .wallpaper_metadata(&wallpaper, &config.naming, collection.name())?;
+        let url = Url::parse(&metadata.image_url).map_err(|err| {
+            Error::Api(format!(
+                "invalid image url '{}': {}",
+                metadata.image_url, err
+            ))
+        })?;
+
+        self.save_new(&collection, &mut local, metadata, &url)
+            .map(Some)
+    }
+
+    /// Downloads the image of `url`, which isn't a Wallhaven wallpaper, into the local
+    /// collection. The file is named by `naming` after the name of the image.
+    /// Returns None when the image is already in the collection.
+    pub fn add_image(
+        &self,
+        url: &Url,
+        directory: &str,
+        naming: &Naming,
+        tags: Vec<String>,
+        extension: &str,
+    ) -> Result<Option<Metadata>> {
+        let collection = self.storage.collection(directory);
+        let mut local = collection.load_or_init()?;
+
+        if local.iter().any(|e| e.image_url == url.as_str()) {
+            return Ok(None);
+        }
+
+        let image_name = url.path().rsplit('/').next().unwrap_or_default();
+        let filename = naming.filename(&FilenameFields {
+            id: image_name
+                .rsplit_once('.')
+                .map(|(stem, _)| stem)
+                .unwrap_or(image_name),
+            ext: extension,
+            collection: collection.name(),
+            resolution: None,
+            tags: &tags,
+        });
+
+        let metadata = Metadata {
+            filename,
+            tags,
+            source_url: url.to_string(),
+            image_url: url.to_string(),
+            ..Default::default()
+        };
+
+        self.save_new(&collection, &mut local, metadata, url)
+            .map(Some)
+    }
+
+    /// Downloads the image under a name which no other file of the collection has,
+    /// in index.json or on disk, and adds it to the index
+    fn save_new(
+        &self,
+        collection: &storage::Collection,
+        local: &mut Vec<Metadata>,
+        mut metadata: Metadata,
+        url: &Url,
+    ) -> Result<Metadata> {
+        let taken = Mutex::new(
+            local
+                .iter()
+                .map(|e| e.filename.clone())
+                .chain(collection.filenames()?)
+                .collect(),
+        );
+        metadata.filename = reserve(&taken, &metadata.filename);
+
+        let downloaded = self.client.download_part(url, collection)?;
+        let filename = downloaded.format.filename(&metadata.filename);
+        if filename != metadata.filename {
+            metadata.filename = reserve(&taken, &filename);
+        }
+
+        collection.finish_part(url.as_str(), &metadata.filename)?;
+        metadata.sha256 = Some(downloaded.sha256);
+        metadata.size = Some(downloaded.size);
+
+        local.push(metadata.clone());
+        collection.persist(local)?;
+
+        Ok(metadata)
+    }
+
+    /// Archives or deletes the removed wallpapers and drops them from index.json
+    pub fn remove(&self, plan: &mut SyncPlan, removal: Removal, summary: &mut SyncSummary) {
+        let removed = plan
+            .removed()
+            .into_iter()
+            .map(|e| e.filename.clone())
+            .collect::<Vec<String>>();
+
+        if removed.is_empty() {
+            return;
+        }
+
+        let collection = &plan.collection;
+
+        plan.local.retain(|e| {
+            if !removed.contains(&e.filename) {
+                return true;
+            }
+
+            let result = match removal {
+                Removal::Archive => collection.archive(&e.filename),
+                Removal::Delete => collection.remove(&e.filename),
+            };
+
+            match result {
+                Ok(_) => {
+                    summary.removed += 1;
+                    false
+                }
+                Err(err) => {
+                    summary.fail(&e.source_url, &err);
+                    true
+                }
+            }
+        });
+
+        if let Err(err) = collection.persist(&plan.local) {
+            summary.fail(collection.name(), &err);
+        }
+    }
+
+    /// Downloads wallpapers missing from the local collection. index.json is saved after
+    /// every download, so an interrupted run keeps the wallpapers downloaded so far.
+    /// The final save records the Wallhaven collection and the time of the sync.
+    pub fn download<F>(
+        &self,
+        plan: SyncPlan,
+        config: &CollectionConfig,
+        summary: &mut SyncSummary,
+        on_event: F,
+    ) where
+        F: Fn(Event) + Sync,
+    {
+        let SyncPlan {
+            collection,
+            origin,
+            local,
+            remote,
+            ..
+        } = plan;
+        let not_synced = find_not_synced(&remote, &local);
+        let total = not_synced.len();
+        let taken = Mutex::new(local.iter().map(|e| e.filename.clone()).collect());
+        let stored = Mutex::new(local);
+        let persist_error = Mutex::new(None);
+
+        let existing = match self.dedupe {
+            Some(_) => self.existing(&collection).unwrap_or_else(|err| {
+                summary.fail("deduplication", &err);
+                vec![]
+            }),
+            None => vec![],
+        };
+
+        let outcomes = pool::run(&not_synced, self.jobs, |index, e| {
+            on_event(Event::Started {
+                index: index + 1,
+                total,
+                name: &e.url,
+            });
+
+            let failed = |err: Error| {
+                on_event(Event::Failed {
+                    name: &e.url,
+                    error: &err,
+                });
+                Outcome::Failed(format!("{} ({})", e.url, err))
+            };
+
+            let skipped = |reason: String| {
+                on_event(Event::Skipped {
+                    name: &e.url,
+                    reason: &reason,
+                });
+                Outcome::Skipped(format!("{} ({})", e.url, reason))
+            };
+
+            // Listing already tells enough to skip the wallpaper without fetching details
+            let listed = Metadata {
+                dimension_x: e.dimension_x,
+                dimension_y: e.dimension_y,
+                ratio: e.ratio.clone(),
+                file_size: e.file_size,
+                purity: e.purity.clone(),
+                category: e.category.clone(),
+                ..Default::default()
+            };
+            if let Some(reason) = config.filters.rejects_properties(&listed) {
+                return skipped(reason);
+            }
+
+            let mut metadata =
+                match self
+                    .client
+                    .wallpaper_metadata(e, &config.naming, collection.name())
+                {
+                    Ok(value) => value,
+                    Err(err) => return failed(err),
+                };
+
+            if let Some(reason) = config.filters.rejects(&metadata) {
+                return skipped(reason);
+            }
+
+            let url = match Url::parse(&metadata.image_url) {
+                Ok(value) => value,
+                Err(err) => {
+                    return failed(Error::Api(format!(
+                        "invalid image url '{}': {}",
+                        metadata.image_url, err
+                    )))
+                }
+            };
+
+            metadata.filename = reserve(&taken, &metadata.filename);
+
+            let same_wallpaper = existing
+                .iter()
+                .find(|(_, e)| e.source_url == metadata.source_url);
+
+            // Link gets the extension of the file it points to, the url only guessed it
+            if let Some(format) = same_wallpaper
+                .filter(|_| self.dedupe.is_some())
+                .and_then(|(path, _)| path.extension())
+                .and_then(|e| ImageFormat::from_extension(&e.to_string_lossy()))
+            {
+                let filename = format.filename(&metadata.filename);
+                if filename != metadata.filename {
+                    metadata.filename = reserve(&taken, &filename);
+                }
+            }
+
+            match self.link_existing(&collection, &metadata.filename, same_wallpaper) {
+                Some((source, linked)) => {
+                    metadata.sha256 = linked.sha256.clone();
+                    metadata.size = linked.size;
+                    on_event(Event::Linked {
+                        name: &e.url,
+                        source,
+                    });
+                }
+                None => {
+                    let downloaded = match self.client.download_part(&url, &collection) {
+                        Ok(value) => value,
+                        Err(err) => return failed(err),
+                    };
+
+                    let filename = downloaded.format.filename(&metadata.filename);
+                    if filename != metadata.filename {
+                        metadata.filename = reserve(&taken, &filename);
+                    }
+
+                    if let Err(err) = collection.finish_part(url.as_str(), &metadata.filename) {
+                        return failed(err);
+                    }
+
+                    metadata.sha256 = Some(downloaded.sha256);
+                    metadata.size = Some(downloaded.size);
+
+                    // Same image can be part of other collection under different id
+                    let same_content = existing
+                        .iter()
+                        .find(|(_, e)| e.sha256.is_some() && e.sha256 == metadata.sha256);
+
+                    if let Some((source, _)) =
+                        self.link_existing(&collection, &metadata.filename, same_content)
+                    {
+                        on_event(Event::Linked {
+                            name: &e.url,
+                            source,
+                        });
+                    }
+                }
+            }
+
+            let mut stored = stored.lock().unwrap();
+            stored.push(metadata);
+            if let Err(err) = collection.persist(&stored) {
+                persist_error.lock().unwrap().get_or_insert(err);
+            }
+
+            Outcome::New
+        });
+
+        for outcome in outcomes {
+            match outcome {
+                Outcome::New => summary.new += 1,
+                Outcome::Skipped(reason) => {
+                    summary.skipped += 1;
+                    summary.filtered.push(reason);
+                }
+                Outcome::Failed(err) => summary.failed.push(err),
+            }
+        }
+
+        if let Some(err) = persist_error.into_inner().unwrap() {
+            summary.fail(collection.name(), &err);
+        }
+
+        let index = synced_index(&origin, stored.into_inner().unwrap());
+
+        if let Err(err) = collection.persist_index(&index) {
+            summary.fail(collection.name(), &err);
+        }
+    }
+
+    /// Downloads the wallpapers of index.json which are missing on disk again,
+    /// records their new checksums and saves the index. Entries are renamed when
+    /// the image turns out to be in other format than their extension says.
+    pub fn rebuild<F>(
+        &self,
+        collection: &storage::Collection,
+        wallpapers: &mut [Metadata],
+        on_event: F,
+    ) -> Result<()>
+    where
+        F: Fn(Event) + Sync,
+    {
+        let missing = collection
+            .missing(wallpapers)?
+            .into_iter()
+            .map(|e| e.filename.clone())
+            .collect::<Vec<String>>();
+        let to_rebuild = wallpapers
+            .iter()
+            .filter(|e| missing.contains(&e.filename))
+            .collect::<Vec<&Metadata>>();
+        let total = to_rebuild.len();
+
+        let downloaded = pool::run(&to_rebuild, self.jobs, |index, e| {
+            on_event(Event::Started {
+                index: index + 1,
+                total,
+                name: &e.filename,
+            });
+
+            let result = Url::parse(&e.image_url)
+                .map_err(|err| Error::Api(format!("invalid image url '{}': {}", e.image_url, err)))
+                .and_then(|url| self.client.download_into(&url, collection, &e.filename));
+
+            match result {
+                Ok((filename, downloaded)) => Some((e.filename.clone(), filename, downloaded)),
+                Err(err) => {
+                    on_event(Event::Failed {
+                        name: &e.filename,
+                        error: &err,
+                    });
+                    None
+                }
+            }
+        });
+
+        for (filename, saved_as, downloaded) in downloaded.into_iter().flatten() {
+            if let Some(e) = wallpapers.iter_mut().find(|e| e.filename == filename) {
+                e.filename = saved_as;
+                e.sha256 = Some(downloaded.sha256);
+                e.size = Some(downloaded.size);
+            }
+        }
+
+        collection.persist(wallpapers)
+    }
+
+    /// Files of every collection other than `collection`, with their index entries
+    fn existing(&self, collection: &storage::Collection) -> Result<Vec<(PathBuf, Metadata)>> {
+        Ok(self
+            .storage
+            .indexes()?
+            .into_iter()
+            .filter(|(other, _)| other.path() != collection.path())
+            .flat_map(|(other, entries)| {
+                entries
+                    .into_iter()
+                    .map(move |e| (other.path().join(&e.filename), e))
+            })
+            .filter(|(path, _)| path.exists())
+            .collect())
+    }
+
+    /// Links `filename` to the existing file, returns it when the link was created
+    fn link_existing<'a>(
+        &self,
+        collection: &storage::Collection,
+        filename: &str,
+        existing: Option<&'a (PathBuf, Metadata)>,
+    ) -> Option<&'a (PathBuf, Metadata)> {
+        let mode = self.dedupe?;
+        let existing = existing?;
+
+        collection
+            .link(&existing.0, filename, mode)
+            .ok()
+            .map(|_| existing)
+    }
+
+    /// Replaces identical files across the whole storage with links to the first of them,
+    /// and records missing checksums in index.json of every collection. Files which
+    /// don't match the checksum in their index are left alone, `verify` reports them.
+    pub fn dedupe(&self, mode: LinkMode) -> Result<Vec<Duplicate>> {
+        let mut indexes = self.storage.indexes()?;
+
+        let files = indexes
+            .iter()
+            .enumerate()
+            .flat_map(|(index, (_, entries))| (0..entries.len()).map(move |e| (index, e)))
+            .collect::<Vec<(usize, usize)>>();
+
+        let hashes = pool::run(&files, self.jobs, |_, (index, entry)| {
+            let (collection, entries) = &indexes[*index];
+            collection.hash(&entries[*entry].filename)
+        });
+
+        let mut first: HashMap<String, PathBuf> = HashMap::new();
+        let mut changed = vec![false; indexes.len()];
+        let mut duplicates = vec![];
+
+        for ((index, entry), hash) in files.into_iter().zip(hashes) {
+            let Some((size, sha256)) = hash? else {
+                continue;
+            };
+
+            let (collection, entries) = &mut indexes[index];
+            let metadata = &mut entries[entry];
+
+            match &metadata.sha256 {
+                Some(recorded) if *recorded != sha256 => continue,
+                Some(_) => (),
+                None => {
+                    metadata.sha256 = Some(sha256.clone());
+                    metadata.size = Some(size);
+                    changed[index] = true;
+                }
+            }
+
+            let path = collection.path().join(&metadata.filename);
+
+            match first.get(&sha256) {
+                None => {
+                    first.insert(sha256, path);
+                }
+                Some(source) if size == 0 || storage::is_same_file(source, &path) => (),
+                Some(source) => {
+                    collection.link(source, &metadata.filename, mode)?;
+                    duplicates.push(Duplicate {
+                        collection: collection.name().to_owned(),
+                        filename: metadata.filename.clone(),
+                        source: source.clone(),
+                        size,
+                    });
+                }
+            }
+        }
+
+        for ((collection, entries), changed) in indexes.iter().zip(changed) {
+            if changed {
+                collection.persist(entries)?;
+            }
+        }
+
+        Ok(duplicates)
+    }
+
+    /// New filenames of the wallpapers according to `naming`, unchanged ones are left out
+    pub fn renames(
+        &self,
+        collection: &storage::Collection,
+        wallpapers: &[Metadata],
+        naming: &Naming,
+    ) -> Vec<Rename> {
+        let taken = Mutex::new(HashSet::new());
+
+        wallpapers
+            .iter()
+            .map(|e| {
+                let (stem, ext) = e.filename.rsplit_once('.').unwrap_or((&e.filename, ""));
+
+                // New random name would differ on every run, any uuid follows the naming
+                let to = if matches!(naming, Naming::Uuid) && uuid::Uuid::parse_str(stem).is_ok() {
+                    e.filename.clone()
+                } else {
+                    naming.filename(&FilenameFields {
+                        id: &wallpaper_id(e),
+                        ext,
+                        collection: collection.name(),
+                        resolution: e.resolution.as_deref(),
+                        tags: &e.tags,
+                    })
+                };
+
+                Rename {
+                    source_url: e.source_url.clone(),
+                    from: e.filename.clone(),
+                    to: reserve(&taken, &to),
+                }
+            })
+            .filter(|e| e.from != e.to)
+            .collect()
+    }
+
+    /// Checks every wallpaper of the collection, returns the damaged ones with their problem
+    pub fn verify<'a>(
+        &self,
+        collection: &storage::Collection,
+        wallpapers: &'a [Metadata],
+    ) -> Vec<(&'a Metadata, Problem)> {
+        pool::run(wallpapers, self.jobs, |_, e| match collection.check(e) {
+            Ok(value) => value,
+            Err(err) => Some(Problem::Unreadable(err.to_string())),
+        })
+        .into_iter()
+        .zip(wallpapers)
+        .filter_map(|(problem, e)| problem.map(|problem| (e, problem)))
+        .collect()
+    }
+}
+
+/// Returns `filename`, or when the template gave the same name to other wallpaper,
+/// the filename with the lowest free `-<n>` suffix. The returned name is marked as taken.
+fn reserve(taken: &Mutex<HashSet<String>>, filename: &str) -> String {
+    let mut taken = taken.lock().unwrap();
+
+    let (stem, ext) = match filename.rsplit_once('.') {
+        Some((stem, ext)) => (stem, format!(".{}", ext)),
+        None => (filename, String::new()),
+    };
+
+    let filename = (1..)
+        .map(|n| match n {
+            1 => filename.to_owned(),
+            n => format!("{}-{}{}", stem, n, ext),
+        })
+        .find(|e| !taken.contains(e))
+        .expect("there is always a free name");
+
+    taken.insert(filename.clone());
+    filename
+}
+
+/// Wallhaven id from the url of the wallpaper page, manually added wallpapers
+/// use name of the image file instead
+fn wallpaper_id(wallpaper: &Metadata) -> String {
+    let url = match wallpaper.source_url.contains("/w/") {
+        true => &wallpaper.source_url,
+        false => &wallpaper.image_url,
+    };
+
+    let name = url.rsplit('/').next().unwrap_or(url);
+    match name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem.to_owned(),
+        _ => name.to_owned(),
+    }
+}
+
+/// Index of the collection synchronized with `origin` just now
+fn synced_index(origin: &Origin, wallpapers: Vec<Metadata>) -> Index {
+    let mut index = Index {
+        last_synced: Some(httpdate::fmt_http_date(SystemTime::now())),
+        ..Index::new(wallpapers)
+    };
+
+    match origin {
+        Origin::Collection { username, id } => {
+            index.collection_id = Some(*id);
+            index.username = Some(username.clone());
+        }
+        Origin::Search(query) => index.search = Some(query.as_ref().clone()),
+    }
+
+    index
+}
+
+pub fn find_not_synced<'a>(
+    from_wallheaven: &'a [Wallpaper],
+    from_collection: &[Metadata],
+) -> Vec<&'a Wallpaper> {
+    let mut not_synced: Vec<&Wallpaper> = vec![];
+    for x in from_wallheaven {
+        let mut contains = false;
+        for y in from_collection {
+            if *x.url == *y.source_url {
+                contains = true;
+                break;
+            }
+        }
+        if !contains {
+            not_synced.push(x);
+        }
+    }
+
+    not_synced
+}
+
+/// Wallpapers which were synchronized from the collection, but are not part of it anymore.
+/// Manually added wallpapers use the image url as source url, they are never reported.
+pub fn find_removed<'a>(
+    from_wallheaven: &[Wallpaper],
+    from_collection: &'a [Metadata],
+) -> Vec<&'a Metadata> {
+    from_collection
+        .iter()
+        .filter(|e| e.source_url != e.image_url)
+        .filter(|e| !from_wallheaven.iter().any(|w| w.url == e.source_url))
+        .collect()
+}
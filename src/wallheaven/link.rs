@@ -0,0 +1,96 @@
+use std::str::FromStr;
+
+use reqwest::Url;
+
+use super::models::SearchQuery;
+
+/// Hosts of the Wallhaven website, `whvn.cc` serves short links to wallpapers
+const HOSTS: [&str; 2] = ["wallhaven.cc", "www.wallhaven.cc"];
+const SHORT_HOST: &str = "whvn.cc";
+
+/// What a link to the Wallhaven website points to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Link {
+    /// Search, toplist or latest wallpapers, or uploads of a user
+    Search(Box<SearchQuery>),
+    /// Favorites collection `id` of the user
+    Collection { username: String, id: i32 },
+    /// Single wallpaper
+    Wallpaper(String),
+}
+
+impl FromStr for Link {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let url = Url::parse(value).map_err(|err| format!("Invalid url '{}': {}", value, err))?;
+        let host = url.host_str().unwrap_or_default();
+        let segments = url
+            .path_segments()
+            .map(|e| e.filter(|e| !e.is_empty()).collect::<Vec<&str>>())
+            .unwrap_or_default();
+
+        let unsupported = || {
+            format!(
+                "'{}' is not a link to a Wallhaven search, collection or wallpaper",
+                value
+            )
+        };
+
+        if host == SHORT_HOST {
+            return match segments.as_slice() {
+                [id] => Ok(Link::Wallpaper(id.to_string())),
+                _ => Err(unsupported()),
+            };
+        }
+
+        if !HOSTS.contains(&host) {
+            return Err(unsupported());
+        }
+
+        match segments.as_slice() {
+            ["w", id] => Ok(Link::Wallpaper(id.to_string())),
+            ["user", username, "favorites", id] => Ok(Link::Collection {
+                username: username.to_string(),
+                id: id.parse().map_err(|_| unsupported())?,
+            }),
+            ["user", username, "uploads"] => Ok(Link::Search(Box::new(SearchQuery {
+                uploader: Some(username.to_string()),
+                ..search_query(&url)
+            }))),
+            ["search"] => Ok(Link::Search(Box::new(search_query(&url)))),
+            ["toplist"] => Ok(Link::Search(Box::new(SearchQuery {
+                sorting: Some("toplist".to_owned()),
+                ..search_query(&url)
+            }))),
+            ["latest"] => Ok(Link::Search(Box::new(SearchQuery {
+                sorting: Some("date_added".to_owned()),
+                ..search_query(&url)
+            }))),
+            _ => Err(unsupported()),
+        }
+    }
+}
+
+/// Parameters of the search page which the API understands, the page is left out
+fn search_query(url: &Url) -> SearchQuery {
+    let mut query = SearchQuery::default();
+
+    for (name, value) in url.query_pairs() {
+        let value = Some(value.into_owned()).filter(|e| !e.is_empty());
+
+        match name.as_ref() {
+            "q" => query.q = value,
+            "categories" => query.categories = value,
+            "purity" => query.purity = value,
+            "atleast" => query.atleast = value,
+            "ratios" => query.ratios = value,
+            "colors" => query.colors = value,
+            "sorting" => query.sorting = value,
+            "topRange" => query.top_range = value,
+            _ => {}
+        }
+    }
+
+    query
+}
@@ -1,159 +1,279 @@
-use core::panic;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
-
-use std::thread::sleep;
-use std::time::Duration;
-
 use reqwest::blocking::Client;
 use reqwest::blocking::ClientBuilder;
-use reqwest::blocking::RequestBuilder;
-use reqwest::blocking::Response;
-use reqwest::header::RETRY_AFTER;
+use reqwest::header::HeaderMap;
+use reqwest::header::HeaderName;
+use reqwest::header::HeaderValue;
+use reqwest::StatusCode;
+use reqwest::Url;
+use serde::de::DeserializeOwned;
 
-use crate::prompts;
+use crate::config::{FilenameFields, Naming};
+use crate::error::{Error, Result};
 use crate::storage::models::Metadata;
+use crate::storage::{self, ImageFormat};
+use crate::webclient;
+use crate::webclient::{Downloaded, RateLimiter, RetryPolicy};
 
 use self::models::Wallpaper;
 
-use self::models::Collection;
+use self::models::{Collection, SearchQuery};
 
+pub mod link;
 pub mod models;
 
-pub fn get_collections(username: &str) -> Vec<Collection> {
-    let url = format!("https://wallhaven.cc/api/v1/collections/{}", username);
-    let body = reqwest::blocking::get(&url)
-        .expect("Failed to fetch collections for user")
-        .text()
-        .unwrap();
-
-    match serde_json::from_str::<models::CollectionsResponse>(&body) {
-        Ok(value) => value.data,
-        Err(_) => match serde_json::from_str::<models::CollectionsErrorResponse>(&body) {
-            Ok(value) => match value.error.as_str() {
-                "Nothing here" => vec![],
-                value => panic!("Unhandled error response: {}", value),
-            },
-            Err(err) => panic!("failed to parse wallheaven API response: {}", err),
-        },
-    }
+pub const DEFAULT_BASE_URL: &str = "https://wallhaven.cc/api/v1/";
+
+/// Page of the wallpaper on the Wallhaven website
+pub fn wallpaper_url(id: &str) -> String {
+    format!("https://wallhaven.cc/w/{}", id)
 }
 
-pub fn get_wallpapers_from_collection(username: &str, collection_id: i32) -> Vec<Wallpaper> {
-    let mut page = 1;
+/// Client of the Wallhaven API. Requests to the API carry the API key and share
+/// the rate limit of the client, images are downloaded with a separate client without them.
+pub struct WallhavenClient {
+    api: Client,
+    files: Client,
+    base_url: Url,
+    max_size: Option<u64>,
+    retry: RetryPolicy,
+    limiter: RateLimiter,
+}
 
-    let mut wallpapers: Vec<Wallpaper> = vec![];
+impl WallhavenClient {
+    pub fn new(api_key: Option<&str>) -> Result<WallhavenClient> {
+        Ok(WallhavenClient {
+            api: get_client(api_key)?,
+            files: webclient::create_client()?,
+            base_url: Url::parse(DEFAULT_BASE_URL).expect("default base url is valid"),
+            max_size: None,
+            retry: RetryPolicy::default(),
+            limiter: RateLimiter::new(),
+        })
+    }
 
-    loop {
-        let url = format!(
-            "https://wallhaven.cc/api/v1/collections/{}/{}?page={}",
-            username, collection_id, page
-        );
+    /// Sends the API requests to `base_url` instead of Wallhaven, for mirrors and tests
+    pub fn with_base_url(mut self, mut base_url: Url) -> WallhavenClient {
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+        self.base_url = base_url;
+        self
+    }
 
-        let body = reqwest::blocking::get(&url).unwrap().text().unwrap();
+    /// Images larger than `max_size` bytes are not downloaded
+    pub fn with_max_size(mut self, max_size: Option<u64>) -> WallhavenClient {
+        self.max_size = max_size;
+        self
+    }
 
-        let mut response: models::CollectionWallpapersResponse =
-            serde_json::from_str(&body).unwrap();
+    /// Failed requests and interrupted downloads are repeated according to `retry`
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> WallhavenClient {
+        self.retry = retry;
+        self
+    }
 
-        wallpapers.append(&mut response.data);
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
 
-        if response.meta.last_page < page {
-            break;
-        }
+    fn endpoint(&self, path: &str) -> Result<Url> {
+        self.base_url
+            .join(path)
+            .map_err(|err| Error::Config(format!("Invalid API url '{}': {}", path, err)))
+    }
+
+    pub fn collections(&self, username: &str) -> Result<Vec<Collection>> {
+        let url = self.endpoint(&format!("collections/{}", username))?;
 
-        page += 1;
+        match self.get_json::<models::CollectionsResponse>(url, "collections") {
+            Ok(value) => Ok(value.data),
+            Err(Error::Api(error)) if error == "Nothing here" => Ok(vec![]),
+            Err(err) => Err(err),
+        }
     }
 
-    wallpapers
-}
+    pub fn collection_wallpapers(
+        &self,
+        username: &str,
+        collection_id: i32,
+    ) -> Result<Vec<Wallpaper>> {
+        let url = self.endpoint(&format!("collections/{}/{}", username, collection_id))?;
 
-pub fn download_wallpaper_metadata(wallpaper: &Wallpaper) -> Metadata {
-    let url = format!("https://wallhaven.cc/api/v1/w/{}", &wallpaper.id);
-    let client = get_client();
-    let request_builder = client.get(url);
-    let body = repeating_text(&client, request_builder);
-
-    let response: models::WallpaperDetailsResponse = serde_json::from_str(&body).unwrap();
-    let original_thumb = response.data.path;
-    let image_extension = match original_thumb.split(".").last() {
-        Some(extension) => extension,
-        None => "jpg",
-    };
-    let uuid = uuid::Uuid::new_v4();
-
-    Metadata {
-        filename: format!("{}.{}", uuid.to_string(), image_extension),
-        tags: response.data.tags.into_iter().map(|e| e.name).collect(),
-        source_url: wallpaper.url.clone(),
-        image_url: original_thumb,
+        self.pages(url, "collection", None, None)
     }
-}
 
-pub fn save_image_content(
-    url: &str,
-    storage_path: &PathBuf,
-    collection_name: &str,
-    filename: &str,
-) {
-    let client = get_client();
-    let request_builder = client.get(url);
-
-    let body = repeating_bytes(&client, request_builder);
-    let full_path = storage_path.join(collection_name).join(filename);
-
-    let mut file = match File::create(full_path) {
-        Ok(file) => file,
-        Err(err) => {
-            panic!("{}", err)
-        }
-    };
+    /// Wallpapers found by the search, up to the limits of the query
+    pub fn search(&self, query: &SearchQuery) -> Result<Vec<Wallpaper>> {
+        let mut url = self.endpoint("search")?;
+        url.query_pairs_mut().extend_pairs(query.params());
 
-    let _ = file.write_all(&body);
-}
+        self.pages(url, "search", query.page_limit(), query.max_wallpapers)
+    }
 
-fn get_client() -> Client {
-    ClientBuilder::new()
-        .user_agent("wallheaven_sync/pietrzyk.jakub001@gmail.com")
-        .build()
-        .unwrap()
-}
+    /// Fetches pages of wallpapers from `url` until the last page or one of the limits
+    fn pages(
+        &self,
+        url: Url,
+        context: &str,
+        max_pages: Option<u32>,
+        max_wallpapers: Option<usize>,
+    ) -> Result<Vec<Wallpaper>> {
+        let mut page = 1;
 
-fn repeating_text(client: &Client, request: RequestBuilder) -> String {
-    repeating(client, request).text().unwrap()
-}
+        let mut wallpapers: Vec<Wallpaper> = vec![];
 
-fn repeating_bytes(client: &Client, request: RequestBuilder) -> Vec<u8> {
-    repeating(client, request).bytes().unwrap().to_vec()
-}
+        loop {
+            let mut url = url.clone();
+            url.query_pairs_mut().append_pair("page", &page.to_string());
+
+            let mut response: models::CollectionWallpapersResponse =
+                self.get_json(url, &format!("{} page {}", context, page))?;
 
-fn repeating(client: &Client, request: RequestBuilder) -> Response {
-    let request = request.build().expect("failed to build request");
+            wallpapers.append(&mut response.data);
 
-    loop {
-        let response = match client.execute(request.try_clone().unwrap()) {
-            Ok(value) => value,
-            Err(_) => {
-                panic!("Failed to get response")
+            if let Some(limit) = max_wallpapers {
+                if wallpapers.len() >= limit {
+                    wallpapers.truncate(limit);
+                    break;
+                }
             }
-        };
-
-        match response.headers().get(RETRY_AFTER) {
-            Some(value) => {
-                let retry_after = value.to_str().unwrap();
-                prompts::info(&format!(
-                    "Reached request per minute limit, waiting {} seconds...",
-                    retry_after
-                ));
-                sleep(Duration::from_secs_f64(
-                    retry_after
-                        .parse::<f64>()
-                        .expect("retry-after header is not numeric value"),
-                ));
+
+            if page >= response.meta.last_page || max_pages.is_some_and(|e| page as u32 >= e) {
+                break;
             }
-            None => (),
-        };
 
-        return response;
+            page += 1;
+        }
+
+        Ok(wallpapers)
+    }
+
+    /// Fetches details of the wallpaper and names the file according to `naming`
+    pub fn wallpaper_metadata(
+        &self,
+        wallpaper: &Wallpaper,
+        naming: &Naming,
+        collection: &str,
+    ) -> Result<Metadata> {
+        let url = self.endpoint(&format!("w/{}", &wallpaper.id))?;
+
+        let response: models::WallpaperDetailsResponse = self.get_json(url, "wallpaper details")?;
+        let details = response.data;
+        let original_thumb = details.path;
+        // The extension is corrected to the format of the content once downloaded
+        let image_extension = ImageFormat::from_url(&original_thumb)
+            .unwrap_or(ImageFormat::Jpeg)
+            .extension();
+        let tags = details
+            .tags
+            .into_iter()
+            .map(|e| e.name)
+            .collect::<Vec<String>>();
+
+        let filename = naming.filename(&FilenameFields {
+            id: &wallpaper.id,
+            ext: image_extension,
+            collection,
+            resolution: details.resolution.as_deref(),
+            tags: &tags,
+        });
+
+        Ok(Metadata {
+            filename,
+            tags,
+            source_url: wallpaper.url.clone(),
+            image_url: original_thumb,
+            resolution: details.resolution,
+            dimension_x: details.dimension_x,
+            dimension_y: details.dimension_y,
+            ratio: details.ratio,
+            file_size: details.file_size,
+            file_type: details.file_type,
+            purity: details.purity,
+            category: details.category,
+            colors: details.colors,
+            views: details.views,
+            favorites: details.favorites,
+            uploader: details.uploader.map(|uploader| uploader.username),
+            source: details.source.filter(|source| !source.is_empty()),
+            created_at: details.created_at,
+            ..Default::default()
+        })
+    }
+
+    /// Streams the image into a partial file of the collection, which the next call resumes
+    /// when the download fails. `Collection::finish_part` moves the finished file in place.
+    pub fn download_part(&self, url: &Url, collection: &storage::Collection) -> Result<Downloaded> {
+        let part_path = collection.part_path(url.as_str());
+
+        webclient::download_image_resumable(
+            &self.files,
+            url,
+            &part_path,
+            self.max_size,
+            &self.retry,
+        )
+    }
+
+    /// Downloads the image into the collection as `filename` with the extension corrected
+    /// to the downloaded format, returns the final filename
+    pub fn download_into(
+        &self,
+        url: &Url,
+        collection: &storage::Collection,
+        filename: &str,
+    ) -> Result<(String, Downloaded)> {
+        let downloaded = self.download_part(url, collection)?;
+        let filename = downloaded.format.filename(filename);
+
+        collection.finish_part(url.as_str(), &filename)?;
+        Ok((filename, downloaded))
+    }
+
+    /// Sends the API request and parses the response. Error responses of the API are
+    /// mapped to `Error::Api`, or `Error::Unauthorized` when the API key is rejected.
+    fn get_json<T: DeserializeOwned>(&self, url: Url, context: &str) -> Result<T> {
+        let response = webclient::repeating(
+            &self.api,
+            self.api.get(url),
+            &self.retry,
+            Some(&self.limiter),
+        )?;
+        let status = response.status();
+        let path = response.url().path().to_owned();
+        let body = response.text().map_err(Error::transport(&path))?;
+
+        if status == StatusCode::UNAUTHORIZED {
+            return Err(Error::Unauthorized);
+        }
+
+        match serde_json::from_str::<T>(&body) {
+            Ok(value) => Ok(value),
+            Err(err) => match serde_json::from_str::<models::CollectionsErrorResponse>(&body) {
+                Ok(value) if value.error == "Unauthorized" => Err(Error::Unauthorized),
+                Ok(value) => Err(Error::Api(value.error)),
+                Err(_) if !status.is_success() => Err(Error::HttpStatus { status, path }),
+                Err(_) => Err(Error::json(context)(err)),
+            },
+        }
+    }
+}
+
+/// Creates client which sends the API key in `X-API-Key` header of every request.
+/// The header is marked as sensitive, so it is never printed in debug output.
+fn get_client(api_key: Option<&str>) -> Result<Client> {
+    let mut headers = HeaderMap::new();
+
+    if let Some(api_key) = api_key {
+        let mut value = HeaderValue::from_str(api_key)
+            .map_err(|_| Error::Config("API key contains invalid characters".to_owned()))?;
+        value.set_sensitive(true);
+        headers.insert(HeaderName::from_static("x-api-key"), value);
     }
+
+    ClientBuilder::new()
+        .user_agent("wallheaven_sync/pietrzyk.jakub001@gmail.com")
+        .default_headers(headers)
+        .build()
+        .map_err(Error::transport("client"))
 }
@@ -23,11 +23,98 @@ pub struct CollectionWallpapersResponse {
     pub data: Vec<Wallpaper>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Wallpaper of a collection or search listing, which lacks the tags
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Wallpaper {
     pub url: String,
     pub path: String,
     pub id: String,
+    #[serde(default)]
+    pub dimension_x: Option<u32>,
+    #[serde(default)]
+    pub dimension_y: Option<u32>,
+    #[serde(default)]
+    pub ratio: Option<String>,
+    #[serde(default)]
+    pub file_size: Option<u64>,
+    #[serde(default)]
+    pub purity: Option<String>,
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+/// Parameters of a search, named like the query parameters of the API. Results are
+/// fetched until `max_pages` pages or `max_wallpapers` wallpapers are collected, without
+/// either of them only the first page is fetched, or every page for `uploader`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchQuery {
+    /// Search terms, for example `nature -city`, `@username` or `like:94x38z`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub q: Option<String>,
+    /// Only uploads of this Wallhaven user, added to `q` as `@username`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uploader: Option<String>,
+    /// Flags of general, anime and people categories, for example `110`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub categories: Option<String>,
+    /// Flags of sfw, sketchy and nsfw purity, for example `100`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub purity: Option<String>,
+    /// Minimal resolution, for example `1920x1080`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub atleast: Option<String>,
+    /// Comma separated aspect ratios, for example `16x9,16x10`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ratios: Option<String>,
+    /// Hex code of the color without #, for example `660000`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub colors: Option<String>,
+    /// date_added, relevance, random, views, favorites or toplist
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sorting: Option<String>,
+    /// Period of the toplist, for example `1M`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_range: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_pages: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_wallpapers: Option<usize>,
+}
+
+impl SearchQuery {
+    /// Query parameters of the API which are set
+    pub fn params(&self) -> Vec<(&'static str, String)> {
+        let q = match (&self.uploader, &self.q) {
+            (Some(uploader), Some(q)) => Some(format!("@{} {}", uploader, q)),
+            (Some(uploader), None) => Some(format!("@{}", uploader)),
+            (None, q) => q.clone(),
+        };
+
+        [
+            ("q", &q),
+            ("categories", &self.categories),
+            ("purity", &self.purity),
+            ("atleast", &self.atleast),
+            ("ratios", &self.ratios),
+            ("colors", &self.colors),
+            ("sorting", &self.sorting),
+            ("topRange", &self.top_range),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.clone().map(|value| (name, value)))
+        .collect()
+    }
+
+    /// Number of pages fetched at most, None when the search isn't limited by pages
+    pub fn page_limit(&self) -> Option<u32> {
+        match (self.max_pages, self.max_wallpapers) {
+            (Some(pages), _) => Some(pages),
+            (None, Some(_)) => None,
+            (None, None) if self.uploader.is_some() => None,
+            (None, None) => Some(1),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -45,6 +132,39 @@ pub struct WallpaperDetailsResponseData {
     pub thumbs: Thumb,
     pub tags: Vec<Tag>,
     pub path: String,
+    #[serde(default)]
+    pub resolution: Option<String>,
+    #[serde(default)]
+    pub dimension_x: Option<u32>,
+    #[serde(default)]
+    pub dimension_y: Option<u32>,
+    #[serde(default)]
+    pub ratio: Option<String>,
+    #[serde(default)]
+    pub file_size: Option<u64>,
+    #[serde(default)]
+    pub file_type: Option<String>,
+    #[serde(default)]
+    pub purity: Option<String>,
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub colors: Vec<String>,
+    #[serde(default)]
+    pub views: Option<u64>,
+    #[serde(default)]
+    pub favorites: Option<u64>,
+    #[serde(default)]
+    pub uploader: Option<Uploader>,
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Uploader {
+    pub username: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -1,70 +1,468 @@
-use std::{thread::sleep, time::Duration};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
+    path::Path,
+    sync::{Mutex, OnceLock},
+    thread::sleep,
+    time::{Duration, Instant, SystemTime},
+};
 
 use reqwest::{
     blocking::{Client, ClientBuilder, RequestBuilder, Response},
-    header::{HeaderName, RETRY_AFTER},
+    header::{HeaderName, CONTENT_RANGE, CONTENT_TYPE, RANGE, RETRY_AFTER},
     StatusCode, Url,
 };
 
-use crate::prompts;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+use crate::storage::ImageFormat;
 
-pub fn download_image(url: &Url) -> Result<Vec<u8>, String> {
-    download_image_with_client(&create_client(), url)
+/// Size of the buffer used to copy the response to disk
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Downloaded image
+#[derive(Debug, Clone, PartialEq)]
+pub struct Downloaded {
+    /// Size in bytes
+    pub size: u64,
+    /// Hex encoded SHA-256 of the content
+    pub sha256: String,
+    /// Format detected from the content, or from `Content-Type` when the content
+    /// isn't recognized
+    pub format: ImageFormat,
 }
 
-fn download_image_with_client(client: &Client, url: &Url) -> Result<Vec<u8>, String> {
-    let request = client
-        .get(url.clone())
-        .header(HeaderName::from_static("accept"), "image/*");
+/// Streams the image to `part_path`, an existing partial file is resumed with a `Range`
+/// request when the server supports it. Interrupted transfers are resumed according to
+/// `policy`. Content is hashed while it is written, images larger than
+/// `max_size` are rejected and their partial file is removed.
+pub(crate) fn download_image_resumable(
+    client: &Client,
+    url: &Url,
+    part_path: &Path,
+    max_size: Option<u64>,
+    policy: &RetryPolicy,
+) -> Result<Downloaded> {
+    let mut attempt = 0;
+    let part_error =
+        |action: &str| Error::io(format!("Failed to {} '{}'", action, part_path.display()));
+
+    loop {
+        let offset = fs::metadata(part_path).map(|e| e.len()).unwrap_or(0);
+
+        let mut request = client
+            .get(url.clone())
+            .header(HeaderName::from_static("accept"), "image/*");
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", offset));
+        }
+
+        // Image host has no request limit, its 429 responses only delay this request
+        let mut response = repeating(client, request, policy, None)?;
+
+        let append = match response.status() {
+            StatusCode::PARTIAL_CONTENT if content_range_start(&response) == Some(offset) => true,
+            StatusCode::OK => false,
+            // Partial file doesn't match the image anymore, start from the beginning
+            StatusCode::PARTIAL_CONTENT | StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => {
+                fs::remove_file(part_path).map_err(part_error("remove"))?;
+                continue;
+            }
+            status => {
+                return Err(Error::HttpStatus {
+                    status,
+                    path: url.path().to_owned(),
+                })
+            }
+        };
+
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        // Error pages are not worth downloading
+        if let Some(value) = content_type
+            .as_deref()
+            .filter(|e| e.starts_with("text/html"))
+        {
+            return Err(not_image(url, Some(value)));
+        }
 
-    let response = repeating(&client, request);
+        let mut hasher = Sha256::new();
+        let mut size = 0;
 
-    match response.status() {
-        StatusCode::OK => Ok(response.bytes().unwrap().to_vec()),
-        _ => {
-            return Err(format!(
-                "Unhandled response code received in client, {}",
-                response.status()
-            ))
+        if append {
+            let mut existing = File::open(part_path).map_err(part_error("read"))?;
+            size = io::copy(&mut existing, &mut hasher).map_err(part_error("read"))?;
+        }
+
+        let too_large = |size: u64| max_size.is_some_and(|limit| size > limit);
+
+        if too_large(size + response.content_length().unwrap_or(0)) {
+            let _ = fs::remove_file(part_path);
+            return Err(Error::TooLarge {
+                path: url.path().to_owned(),
+                limit: max_size.unwrap_or_default(),
+            });
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(part_path)
+            .map_err(part_error("open"))?;
+
+        let mut buffer = vec![0; COPY_BUFFER_SIZE];
+
+        let result = loop {
+            let read = match response.read(&mut buffer) {
+                Ok(0) => break file.sync_all(),
+                Ok(read) => read,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => break Err(err),
+            };
+
+            size += read as u64;
+
+            // Server didn't announce the size or sent more than it announced
+            if too_large(size) {
+                drop(file);
+                let _ = fs::remove_file(part_path);
+                return Err(Error::TooLarge {
+                    path: url.path().to_owned(),
+                    limit: max_size.unwrap_or_default(),
+                });
+            }
+
+            hasher.update(&buffer[..read]);
+
+            if let Err(err) = file.write_all(&buffer[..read]) {
+                break Err(err);
+            }
+        };
+
+        attempt += 1;
+
+        match result {
+            Ok(_) => {
+                let mut head = Vec::with_capacity(ImageFormat::SNIFF_LENGTH);
+                File::open(part_path)
+                    .and_then(|file| {
+                        file.take(ImageFormat::SNIFF_LENGTH as u64)
+                            .read_to_end(&mut head)
+                    })
+                    .map_err(part_error("read"))?;
+
+                let format = ImageFormat::sniff(&head).or_else(|| {
+                    content_type
+                        .as_deref()
+                        .and_then(ImageFormat::from_content_type)
+                });
+
+                return match format {
+                    Some(format) => Ok(Downloaded {
+                        size,
+                        sha256: format!("{:x}", hasher.finalize()),
+                        format,
+                    }),
+                    None => {
+                        let _ = fs::remove_file(part_path);
+                        Err(not_image(url, content_type.as_deref()))
+                    }
+                };
+            }
+            Err(_) if attempt < policy.max_attempts => {
+                let delay = policy.delay(attempt);
+                notify(&format!(
+                    "Download of {} was interrupted, resuming in {:.1} seconds...",
+                    url.path(),
+                    delay.as_secs_f64()
+                ));
+                sleep(delay);
+            }
+            Err(err) => return Err(Error::io(format!("Failed to download {}", url.path()))(err)),
         }
     }
 }
 
-fn create_client() -> Client {
+fn not_image(url: &Url, content_type: Option<&str>) -> Error {
+    Error::NotImage {
+        path: url.path().to_owned(),
+        content_type: content_type.unwrap_or("none").to_owned(),
+    }
+}
+
+/// First byte of the partial response, from `Content-Range: bytes <start>-<end>/<size>`
+fn content_range_start(response: &Response) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .parse()
+        .ok()
+}
+
+pub(crate) fn create_client() -> Result<Client> {
     ClientBuilder::new()
         .user_agent("wallheaven_sync/pietrzyk.jakub001@gmail.com")
         .build()
-        .unwrap()
+        .map_err(Error::transport("client"))
+}
+
+/// How failed requests are repeated
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Number of attempts, including the first one
+    pub max_attempts: u32,
+    /// Delay before the second attempt, doubled for every next one
+    pub base_delay_ms: u64,
+    /// Upper bound of the delay
+    pub max_delay_ms: u64,
+    /// Fraction of the delay which is randomized, from 0 to 1
+    pub jitter: f64,
+    /// Status codes which are repeated
+    pub retryable_statuses: Vec<u16>,
 }
 
-fn repeating(client: &Client, request: RequestBuilder) -> Response {
-    let request = request.build().expect("failed to build request");
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay_ms: 1000,
+            max_delay_ms: 30_000,
+            jitter: 0.5,
+            retryable_statuses: vec![408, 500, 502, 503, 504],
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn is_retryable(&self, status: StatusCode) -> bool {
+        self.retryable_statuses.contains(&status.as_u16())
+    }
+
+    /// Delay after given failed attempt, starting from 1
+    fn delay(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay_ms
+            .saturating_mul(2u64.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay_ms) as f64;
+        let jitter = self.jitter.clamp(0.0, 1.0);
+
+        Duration::from_millis((exponential * (1.0 - jitter * fastrand::f64())) as u64)
+    }
+}
+
+static NOTIFIER: OnceLock<fn(&str)> = OnceLock::new();
+
+/// Sets the function which shows messages about waiting and retried requests,
+/// messages are printed to stdout by default
+pub fn set_notifier(notifier: fn(&str)) {
+    let _ = NOTIFIER.set(notifier);
+}
+
+fn notify(message: &str) {
+    match NOTIFIER.get() {
+        Some(notifier) => notifier(message),
+        None => println!("{}", message),
+    }
+}
+
+/// Wallhaven API allows 45 requests per minute
+const REQUESTS_PER_MINUTE: f64 = 45.0;
+
+/// How many times single request is repeated after 429 response
+const MAX_RATE_LIMITED_RETRIES: usize = 5;
+
+/// Used when 429 response doesn't say how long to wait
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Limit of the requests to the Wallhaven API, shared by every thread of the client
+pub(crate) struct RateLimiter {
+    bucket: Mutex<TokenBucket>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new() -> RateLimiter {
+        RateLimiter {
+            bucket: Mutex::new(TokenBucket {
+                tokens: REQUESTS_PER_MINUTE,
+                refilled_at: None,
+            }),
+        }
+    }
+
+    fn acquire(&self) {
+        loop {
+            let result = self.bucket.lock().unwrap().try_acquire();
+
+            match result {
+                Ok(_) => return,
+                Err(wait) => sleep(wait),
+            }
+        }
+    }
+
+    fn pause_for(&self, retry_after: Duration) {
+        self.bucket.lock().unwrap().pause_for(retry_after);
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Option<Instant>,
+}
+
+impl TokenBucket {
+    fn refill(&mut self, now: Instant) {
+        match self.refilled_at {
+            // Paused after 429 response, nothing to refill yet
+            Some(refilled_at) if refilled_at > now => return,
+            Some(refilled_at) => {
+                let elapsed = now.duration_since(refilled_at).as_secs_f64();
+                self.tokens =
+                    (self.tokens + elapsed * REQUESTS_PER_MINUTE / 60.0).min(REQUESTS_PER_MINUTE);
+            }
+            None => (),
+        }
+        self.refilled_at = Some(now);
+    }
+
+    /// Takes a token, or returns how long to wait for the next one
+    fn try_acquire(&mut self) -> std::result::Result<(), Duration> {
+        let now = Instant::now();
+        self.refill(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let paused = self
+            .refilled_at
+            .map(|e| e.saturating_duration_since(now))
+            .unwrap_or_default();
+
+        Err(paused + Duration::from_secs_f64((1.0 - self.tokens) * 60.0 / REQUESTS_PER_MINUTE))
+    }
+
+    /// Called after the server responded with 429, the bucket is emptied and
+    /// refills after `retry_after`
+    fn pause_for(&mut self, retry_after: Duration) {
+        let until = Instant::now() + retry_after;
+        self.tokens = 0.0;
+        if self.refilled_at.is_none_or(|e| e < until) {
+            self.refilled_at = Some(until);
+        }
+    }
+}
+
+/// Parses Retry-After header, which is either number of seconds or HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    match value.trim().parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => {
+            Some(Duration::from_secs_f64(seconds))
+        }
+        Ok(_) => None,
+        Err(_) => httpdate::parse_http_date(value.trim()).ok().map(|date| {
+            date.duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO)
+        }),
+    }
+}
+
+/// Executes the request, waiting for a token of `limiter` first when it is given.
+/// Rate limited responses are repeated after the time requested by the server, which also
+/// pauses `limiter`. Transport errors and retryable status codes are repeated according to `policy`.
+pub(crate) fn repeating(
+    client: &Client,
+    request: RequestBuilder,
+    policy: &RetryPolicy,
+    limiter: Option<&RateLimiter>,
+) -> Result<Response> {
+    let request = request.build().map_err(Error::transport("request"))?;
+
+    let mut attempt = 0;
+    let mut rate_limited_attempt = 0;
 
     loop {
-        let response = match client.execute(request.try_clone().unwrap()) {
+        if let Some(limiter) = limiter {
+            limiter.acquire();
+        }
+
+        let result = client.execute(
+            request
+                .try_clone()
+                .expect("requests without streaming body can be cloned"),
+        );
+        attempt += 1;
+
+        let response = match result {
             Ok(value) => value,
-            Err(_) => {
-                panic!("Failed to get response")
+            Err(err) if !err.is_builder() && attempt < policy.max_attempts => {
+                let delay = policy.delay(attempt);
+                notify(&format!(
+                    "Request to {} failed, retrying in {:.1} seconds...",
+                    request.url().path(),
+                    delay.as_secs_f64()
+                ));
+                sleep(delay);
+                continue;
             }
+            Err(err) => return Err(Error::transport(request.url().path())(err)),
         };
 
-        match response.headers().get(RETRY_AFTER) {
-            Some(value) => {
-                let retry_after = value.to_str().unwrap();
-                prompts::info(&format!(
-                    "Reached request per minute limit, waiting {} seconds...",
-                    retry_after
-                ));
-                sleep(Duration::from_secs_f64(
-                    retry_after
-                        .parse::<f64>()
-                        .expect("retry-after header is not numeric value"),
-                ));
+        let status = response.status();
+
+        if status == StatusCode::TOO_MANY_REQUESTS
+            && rate_limited_attempt < MAX_RATE_LIMITED_RETRIES
+        {
+            // Rate limiting is not a failure, it doesn't count as an attempt
+            attempt -= 1;
+            rate_limited_attempt += 1;
+
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|e| e.to_str().ok())
+                .and_then(parse_retry_after)
+                .unwrap_or(DEFAULT_RETRY_AFTER);
+
+            notify(&format!(
+                "Reached request per minute limit, waiting {} seconds...",
+                retry_after.as_secs()
+            ));
+
+            match limiter {
+                Some(limiter) => limiter.pause_for(retry_after),
+                None => sleep(retry_after),
             }
-            None => (),
-        };
+            continue;
+        }
 
-        return response;
+        if policy.is_retryable(status) && attempt < policy.max_attempts {
+            let delay = policy.delay(attempt);
+            notify(&format!(
+                "Request to {} failed with {}, retrying in {:.1} seconds...",
+                request.url().path(),
+                status,
+                delay.as_secs_f64()
+            ));
+            sleep(delay);
+            continue;
+        }
+
+        return Ok(response);
     }
 }
-
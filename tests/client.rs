@@ -0,0 +1,482 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use mockito::{Matcher, Server};
+use reqwest::Url;
+use wallheaven_sync::{
+    config::Naming,
+    storage::{ImageFormat, Storage},
+    wallheaven::{
+        link::Link,
+        models::{SearchQuery, Wallpaper},
+        WallhavenClient,
+    },
+    webclient::RetryPolicy,
+    Error,
+};
+
+mod common;
+
+fn client(server: &mockito::ServerGuard) -> WallhavenClient {
+    WallhavenClient::new(None)
+        .unwrap()
+        .with_base_url(Url::parse(&common::api_url(server)).unwrap())
+}
+
+#[test]
+fn lists_collections_of_the_user() {
+    let mut server = Server::new();
+    let mock = common::collections(&mut server, "alice", &[(7, "Nature"), (8, "Space")]);
+
+    let collections = client(&server).collections("alice").unwrap();
+
+    mock.assert();
+    let labels = collections
+        .iter()
+        .map(|e| e.label.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(labels, ["Nature", "Space"]);
+    assert_eq!(collections[0].id, 7);
+}
+
+#[test]
+fn api_key_is_sent_only_to_the_api() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    let api = server
+        .mock("GET", "/api/v1/collections/alice")
+        .match_header("x-api-key", "secret")
+        .with_header("content-type", "application/json")
+        .with_body(r#"{"data":[]}"#)
+        .create();
+    let image = server
+        .mock("GET", "/full/aaa.jpg")
+        .match_header("x-api-key", Matcher::Missing)
+        .with_header("content-type", "image/jpeg")
+        .with_body(b"\xff\xd8\xff image")
+        .create();
+    let collection = Storage::new(root.path()).collection("Nature");
+    collection.init().unwrap();
+    let url = Url::parse(&format!("{}/full/aaa.jpg", server.url())).unwrap();
+    let client = WallhavenClient::new(Some("secret"))
+        .unwrap()
+        .with_base_url(Url::parse(&common::api_url(&server)).unwrap());
+
+    client.collections("alice").unwrap();
+    client.download_part(&url, &collection).unwrap();
+
+    api.assert();
+    image.assert();
+}
+
+#[test]
+fn user_without_collections_has_none() {
+    let mut server = Server::new();
+    common::error(
+        &mut server,
+        "/api/v1/collections/nobody",
+        404,
+        "Nothing here",
+    );
+
+    let collections = client(&server).collections("nobody").unwrap();
+
+    assert!(collections.is_empty());
+}
+
+#[test]
+fn rejected_api_key_is_unauthorized() {
+    let mut server = Server::new();
+    common::error(
+        &mut server,
+        "/api/v1/collections/alice",
+        401,
+        "Unauthorized",
+    );
+
+    let result = client(&server).collections("alice");
+
+    assert!(matches!(result, Err(Error::Unauthorized)), "{:?}", result);
+}
+
+#[test]
+fn error_body_is_reported() {
+    let mut server = Server::new();
+    common::error(
+        &mut server,
+        "/api/v1/collections/alice",
+        400,
+        "Invalid username",
+    );
+
+    let result = client(&server).collections("alice");
+
+    assert!(
+        matches!(&result, Err(Error::Api(message)) if message == "Invalid username"),
+        "{:?}",
+        result
+    );
+}
+
+#[test]
+fn non_json_error_is_reported_with_status() {
+    let mut server = Server::new();
+    server
+        .mock("GET", "/api/v1/collections/alice")
+        .with_status(404)
+        .with_header("content-type", "text/html")
+        .with_body("<html>Not found</html>")
+        .create();
+
+    let result = client(&server).collections("alice");
+
+    assert!(
+        matches!(&result, Err(Error::HttpStatus { status, .. }) if status.as_u16() == 404),
+        "{:?}",
+        result
+    );
+}
+
+#[test]
+fn fetches_every_page_of_the_collection() {
+    let mut server = Server::new();
+    let first = common::collection_page(&mut server, "alice", 7, 1, 2, &["a1", "a2"]);
+    let second = common::collection_page(&mut server, "alice", 7, 2, 2, &["b1"]);
+    let past_last = server
+        .mock("GET", "/api/v1/collections/alice/7")
+        .match_query(Matcher::UrlEncoded("page".into(), "3".into()))
+        .expect(0)
+        .create();
+
+    let wallpapers = client(&server).collection_wallpapers("alice", 7).unwrap();
+
+    first.assert();
+    second.assert();
+    past_last.assert();
+    let ids = wallpapers.iter().map(|e| e.id.as_str()).collect::<Vec<_>>();
+    assert_eq!(ids, ["a1", "a2", "b1"]);
+}
+
+#[test]
+fn rate_limited_request_is_repeated() {
+    let mut server = Server::new();
+    let limited = server
+        .mock("GET", "/api/v1/collections/alice")
+        .with_status(429)
+        .with_header("retry-after", "0")
+        .expect(1)
+        .create();
+    let ok = common::collections(&mut server, "alice", &[(7, "Nature")]);
+
+    let collections = client(&server).collections("alice").unwrap();
+
+    limited.assert();
+    ok.assert();
+    assert_eq!(collections.len(), 1);
+}
+
+#[test]
+fn retry_after_date_is_waited_for() {
+    let mut server = Server::new();
+    let retry_at = SystemTime::now() + Duration::from_secs(2);
+    let limited = server
+        .mock("GET", "/api/v1/collections/alice")
+        .with_status(429)
+        .with_header("retry-after", &httpdate::fmt_http_date(retry_at))
+        .expect(1)
+        .create();
+    let ok = common::collections(&mut server, "alice", &[(7, "Nature")]);
+
+    let started = Instant::now();
+    let collections = client(&server).collections("alice").unwrap();
+
+    limited.assert();
+    ok.assert();
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    assert_eq!(collections.len(), 1);
+}
+
+#[test]
+fn rate_limited_image_does_not_pause_api_requests() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    let limited = server
+        .mock("GET", "/full/aaa.jpg")
+        .with_status(429)
+        .with_header("retry-after", "0")
+        .expect(1)
+        .create();
+    let image = common::image(&mut server, "aaa", b"\xff\xd8\xff image");
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    let collection = Storage::new(root.path()).collection("Nature");
+    collection.init().unwrap();
+    let url = Url::parse(&format!("{}/full/aaa.jpg", server.url())).unwrap();
+    let client = client(&server);
+
+    client.download_part(&url, &collection).unwrap();
+    let started = Instant::now();
+    client.collections("alice").unwrap();
+
+    limited.assert();
+    image.assert();
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn server_error_is_retried_until_attempts_run_out() {
+    let mut server = Server::new();
+    let failing = server
+        .mock("GET", "/api/v1/collections/alice")
+        .with_status(503)
+        .expect(1)
+        .create();
+    let ok = common::collections(&mut server, "alice", &[(7, "Nature")]);
+    let retry = RetryPolicy {
+        max_attempts: 2,
+        base_delay_ms: 10,
+        ..Default::default()
+    };
+    let client = client(&server).with_retry_policy(retry);
+
+    assert_eq!(client.collections("alice").unwrap().len(), 1);
+    failing.assert();
+    ok.assert();
+
+    ok.remove();
+    let failing = server
+        .mock("GET", "/api/v1/collections/alice")
+        .with_status(503)
+        .expect(2)
+        .create();
+
+    let result = client.collections("alice");
+
+    failing.assert();
+    assert!(
+        matches!(&result, Err(Error::HttpStatus { status, .. }) if status.as_u16() == 503),
+        "{:?}",
+        result
+    );
+}
+
+#[test]
+fn wallpaper_metadata_is_named_by_id() {
+    let mut server = Server::new();
+    common::wallpaper_details(&mut server, "abc", &["forest", "river"]);
+    let wallpaper = Wallpaper {
+        id: "abc".to_owned(),
+        url: "https://wallhaven.cc/w/abc".to_owned(),
+        path: format!("{}/full/abc.jpg", server.url()),
+        ..Default::default()
+    };
+
+    let metadata = client(&server)
+        .wallpaper_metadata(&wallpaper, &Naming::Id, "Nature")
+        .unwrap();
+
+    assert_eq!(metadata.filename, "abc.jpg");
+    assert_eq!(metadata.tags, ["forest", "river"]);
+    assert_eq!(metadata.source_url, wallpaper.url);
+    assert_eq!(metadata.image_url, wallpaper.path);
+    assert_eq!(metadata.dimension_x, Some(1920));
+    assert_eq!(metadata.dimension_y, Some(1080));
+    assert_eq!(metadata.ratio.as_deref(), Some("1.78"));
+    assert_eq!(metadata.purity.as_deref(), Some("sfw"));
+    assert_eq!(metadata.category.as_deref(), Some("general"));
+    assert_eq!(metadata.colors, ["#424153", "#66cccc"]);
+    assert_eq!(metadata.favorites, Some(7));
+    assert_eq!(metadata.uploader.as_deref(), Some("bob"));
+    assert_eq!(metadata.source, None);
+    assert_eq!(metadata.created_at.as_deref(), Some("2018-10-31 01:23:10"));
+}
+
+#[test]
+fn wallpaper_metadata_is_named_by_template() {
+    let mut server = Server::new();
+    common::wallpaper_details(&mut server, "abc", &["night sky/stars", "forest"]);
+    let wallpaper = Wallpaper {
+        id: "abc".to_owned(),
+        url: "https://wallhaven.cc/w/abc".to_owned(),
+        path: format!("{}/full/abc.jpg", server.url()),
+        ..Default::default()
+    };
+    let client = client(&server);
+
+    let name = |template: &str| {
+        client
+            .wallpaper_metadata(&wallpaper, &template.parse().unwrap(), "Nature")
+            .unwrap()
+            .filename
+    };
+
+    assert_eq!(
+        name("{collection}-{id}-{resolution}.{ext}"),
+        "Nature-abc-1920x1080.jpg"
+    );
+    assert_eq!(name("{first_tag}_{id}.{ext}"), "night_sky_stars_abc.jpg");
+}
+
+#[test]
+fn invalid_templates_are_rejected() {
+    assert!("{id}.{ext}".parse::<Naming>().is_ok());
+    assert!("{name}.{ext}".parse::<Naming>().is_err());
+    assert!("{id}.{ext".parse::<Naming>().is_err());
+    assert!("dir/{id}.{ext}".parse::<Naming>().is_err());
+    assert!("wallpaper".parse::<Naming>().is_err());
+}
+
+#[test]
+fn image_formats_are_recognized() {
+    assert_eq!(
+        ImageFormat::sniff(b"\xff\xd8\xff\xe0"),
+        Some(ImageFormat::Jpeg)
+    );
+    assert_eq!(
+        ImageFormat::sniff(b"RIFF\x00\x00\x00\x00WEBPVP8 "),
+        Some(ImageFormat::Webp)
+    );
+    assert_eq!(
+        ImageFormat::sniff(b"\x00\x00\x00\x1cftypavif"),
+        Some(ImageFormat::Avif)
+    );
+    assert_eq!(ImageFormat::sniff(b"GIF89a"), Some(ImageFormat::Gif));
+    assert_eq!(ImageFormat::sniff(b"<!DOCTYPE html>"), None);
+
+    assert_eq!(
+        ImageFormat::from_url("https://example.com/a/image.WEBP?size=full"),
+        Some(ImageFormat::Webp)
+    );
+    assert_eq!(ImageFormat::from_url("https://example.com/image"), None);
+    assert_eq!(
+        ImageFormat::from_content_type("image/png; charset=binary"),
+        Some(ImageFormat::Png)
+    );
+
+    assert_eq!(ImageFormat::Jpeg.filename("abc.jpeg"), "abc.jpeg");
+    assert_eq!(ImageFormat::Png.filename("abc.jpg"), "abc.png");
+    assert_eq!(ImageFormat::Gif.filename("abc"), "abc.gif");
+}
+
+#[test]
+fn search_sends_the_query_and_stops_at_the_limit() {
+    let mut server = Server::new();
+    let first = server
+        .mock("GET", "/api/v1/search")
+        .match_query(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("q".into(), "night sky".into()),
+            Matcher::UrlEncoded("purity".into(), "100".into()),
+            Matcher::UrlEncoded("topRange".into(), "1M".into()),
+            Matcher::UrlEncoded("page".into(), "1".into()),
+        ]))
+        .with_body(
+            r#"{"meta":{"last_page":5},"data":[{"id":"a","url":"u","path":"p"},{"id":"b","url":"u","path":"p"}]}"#,
+        )
+        .create();
+    let second = common::search_page(&mut server, "night sky", 2, 5, &["c", "d"]);
+    let third = common::search_page(&mut server, "night sky", 3, 5, &["e"]).expect(0);
+
+    let query = SearchQuery {
+        q: Some("night sky".to_owned()),
+        purity: Some("100".to_owned()),
+        top_range: Some("1M".to_owned()),
+        max_wallpapers: Some(3),
+        ..Default::default()
+    };
+    let wallpapers = client(&server).search(&query).unwrap();
+
+    first.assert();
+    second.assert();
+    third.assert();
+    let ids = wallpapers.iter().map(|e| e.id.as_str()).collect::<Vec<_>>();
+    assert_eq!(ids, ["a", "b", "c"]);
+}
+
+#[test]
+fn search_without_limits_fetches_first_page() {
+    let mut server = Server::new();
+    common::search_page(&mut server, "forest", 1, 3, &["a"]);
+    let second = common::search_page(&mut server, "forest", 2, 3, &["b"]).expect(0);
+
+    let query = SearchQuery {
+        q: Some("forest".to_owned()),
+        ..Default::default()
+    };
+    let wallpapers = client(&server).search(&query).unwrap();
+
+    second.assert();
+    assert_eq!(wallpapers.len(), 1);
+}
+
+#[test]
+fn uploads_of_the_user_are_fetched_from_every_page() {
+    let mut server = Server::new();
+    common::search_page(&mut server, "@bob", 1, 3, &["a"]);
+    common::search_page(&mut server, "@bob", 2, 3, &["b"]);
+    common::search_page(&mut server, "@bob", 3, 3, &["c"]);
+    common::search_page(&mut server, "@bob forest", 1, 2, &["d"]);
+    let second = common::search_page(&mut server, "@bob forest", 2, 2, &["e"]).expect(0);
+
+    let uploads = SearchQuery {
+        uploader: Some("bob".to_owned()),
+        ..Default::default()
+    };
+    let wallpapers = client(&server).search(&uploads).unwrap();
+    assert_eq!(wallpapers.len(), 3);
+
+    let limited = SearchQuery {
+        q: Some("forest".to_owned()),
+        max_pages: Some(1),
+        ..uploads
+    };
+    let wallpapers = client(&server).search(&limited).unwrap();
+    second.assert();
+    assert_eq!(wallpapers.len(), 1);
+}
+
+#[test]
+fn links_of_the_website_are_recognized() {
+    let link = |value: &str| value.parse::<Link>();
+
+    assert_eq!(
+        link("https://wallhaven.cc/search?q=night%20sky&categories=110&purity=100&page=3"),
+        Ok(Link::Search(Box::new(SearchQuery {
+            q: Some("night sky".to_owned()),
+            categories: Some("110".to_owned()),
+            purity: Some("100".to_owned()),
+            ..Default::default()
+        })))
+    );
+    assert_eq!(
+        link("https://wallhaven.cc/toplist?topRange=1M"),
+        Ok(Link::Search(Box::new(SearchQuery {
+            sorting: Some("toplist".to_owned()),
+            top_range: Some("1M".to_owned()),
+            ..Default::default()
+        })))
+    );
+    assert_eq!(
+        link("https://wallhaven.cc/user/bob/uploads"),
+        Ok(Link::Search(Box::new(SearchQuery {
+            uploader: Some("bob".to_owned()),
+            ..Default::default()
+        })))
+    );
+    assert_eq!(
+        link("https://wallhaven.cc/user/bob/favorites/123"),
+        Ok(Link::Collection {
+            username: "bob".to_owned(),
+            id: 123
+        })
+    );
+    assert_eq!(
+        link("https://wallhaven.cc/w/abc123"),
+        Ok(Link::Wallpaper("abc123".to_owned()))
+    );
+    assert_eq!(
+        link("https://whvn.cc/abc123"),
+        Ok(Link::Wallpaper("abc123".to_owned()))
+    );
+
+    assert!(link("https://wallhaven.cc/user/bob/favorites/latest").is_err());
+    assert!(link("https://example.com/w/abc123").is_err());
+    assert!(link("wallhaven.cc/w/abc123").is_err());
+}
@@ -0,0 +1,146 @@
+//! Canned Wallhaven API responses shared by the integration tests
+
+#![allow(dead_code)]
+
+use mockito::{Matcher, Mock, ServerGuard};
+use serde_json::json;
+
+pub fn api_url(server: &ServerGuard) -> String {
+    format!("{}/api/v1", server.url())
+}
+
+pub fn collections(server: &mut ServerGuard, username: &str, collections: &[(i32, &str)]) -> Mock {
+    let data = collections
+        .iter()
+        .map(|(id, label)| json!({ "id": id, "label": label, "count": 0 }))
+        .collect::<Vec<_>>();
+
+    server
+        .mock("GET", format!("/api/v1/collections/{}", username).as_str())
+        .with_header("content-type", "application/json")
+        .with_body(json!({ "data": data }).to_string())
+        .create()
+}
+
+/// Page of the collection, every wallpaper is described by its id
+pub fn collection_page(
+    server: &mut ServerGuard,
+    username: &str,
+    collection_id: i32,
+    page: i32,
+    last_page: i32,
+    ids: &[&str],
+) -> Mock {
+    let url = server.url();
+    let data = ids
+        .iter()
+        .map(|id| {
+            json!({
+                "id": id,
+                "url": format!("https://wallhaven.cc/w/{}", id),
+                "path": format!("{}/full/{}.jpg", url, id),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    server
+        .mock(
+            "GET",
+            format!("/api/v1/collections/{}/{}", username, collection_id).as_str(),
+        )
+        .match_query(Matcher::UrlEncoded("page".into(), page.to_string()))
+        .with_header("content-type", "application/json")
+        .with_body(json!({ "meta": { "last_page": last_page }, "data": data }).to_string())
+        .create()
+}
+
+/// Page of search results for the query `q`
+pub fn search_page(
+    server: &mut ServerGuard,
+    q: &str,
+    page: i32,
+    last_page: i32,
+    ids: &[&str],
+) -> Mock {
+    let url = server.url();
+    let data = ids
+        .iter()
+        .map(|id| {
+            json!({
+                "id": id,
+                "url": format!("https://wallhaven.cc/w/{}", id),
+                "path": format!("{}/full/{}.jpg", url, id),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    server
+        .mock("GET", "/api/v1/search")
+        .match_query(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("q".into(), q.into()),
+            Matcher::UrlEncoded("page".into(), page.to_string()),
+        ]))
+        .with_header("content-type", "application/json")
+        .with_body(json!({ "meta": { "last_page": last_page }, "data": data }).to_string())
+        .create()
+}
+
+pub fn wallpaper_details(server: &mut ServerGuard, id: &str, tags: &[&str]) -> Mock {
+    let path = format!("{}/full/{}.jpg", server.url(), id);
+    let tags = tags
+        .iter()
+        .map(|name| json!({ "name": name }))
+        .collect::<Vec<_>>();
+
+    server
+        .mock("GET", format!("/api/v1/w/{}", id).as_str())
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({
+                "data": {
+                    "path": path,
+                    "resolution": "1920x1080",
+                    "dimension_x": 1920,
+                    "dimension_y": 1080,
+                    "ratio": "1.78",
+                    "file_size": 3,
+                    "file_type": "image/jpeg",
+                    "purity": "sfw",
+                    "category": "general",
+                    "colors": ["#424153", "#66cccc"],
+                    "views": 120,
+                    "favorites": 7,
+                    "uploader": { "username": "bob", "group": "User" },
+                    "source": "",
+                    "created_at": "2018-10-31 01:23:10",
+                    "thumbs": { "original": format!("{}/thumb/{}.jpg", server.url(), id) },
+                    "tags": tags,
+                }
+            })
+            .to_string(),
+        )
+        .create()
+}
+
+pub fn image(server: &mut ServerGuard, id: &str, content: &[u8]) -> Mock {
+    image_as(server, id, content, "image/jpeg")
+}
+
+/// Image served with other `Content-Type` than JPEG
+pub fn image_as(server: &mut ServerGuard, id: &str, content: &[u8], content_type: &str) -> Mock {
+    server
+        .mock("GET", format!("/full/{}.jpg", id).as_str())
+        .with_header("content-type", content_type)
+        .with_body(content)
+        .create()
+}
+
+pub fn error(server: &mut ServerGuard, path: &str, status: usize, message: &str) -> Mock {
+    server
+        .mock("GET", path)
+        .match_query(Matcher::Any)
+        .with_status(status)
+        .with_header("content-type", "application/json")
+        .with_body(json!({ "error": message }).to_string())
+        .create()
+}
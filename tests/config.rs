@@ -0,0 +1,26 @@
+use std::fs;
+
+use wallheaven_sync::config::{Config, Settings};
+
+#[test]
+fn api_key_is_redacted_in_debug_output() {
+    let root = tempfile::tempdir().unwrap();
+    let path = root.path().join("config.toml");
+    fs::write(&path, "api_key = \"secret-key\"\n").unwrap();
+
+    let config: Config = toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    let settings = Settings::resolve(
+        Some(path),
+        Some(root.path().join("storage")),
+        None,
+        None,
+        None,
+    )
+    .unwrap();
+
+    for output in [format!("{:?}", config), format!("{:?}", settings)] {
+        assert!(!output.contains("secret-key"), "{}", output);
+        assert!(output.contains("<redacted>"), "{}", output);
+    }
+    assert!(settings.api_key().is_some());
+}
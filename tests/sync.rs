@@ -0,0 +1,1089 @@
+use std::{fs, path::Path, process::Command};
+
+use mockito::Server;
+use serde_json::Value;
+
+mod common;
+
+/// Writes config which points the binary at the mock server, `extra` is added to the top level
+fn write_config(server: &mockito::ServerGuard, root: &Path, extra: &str) {
+    fs::write(
+        root.join("config.toml"),
+        format!(
+            "username = \"alice\"\nstorage_path = {:?}\nbase_url = {:?}\n{}\n[collection.Nature]\nnaming = \"id\"\n",
+            root.join("storage"),
+            common::api_url(server),
+            extra,
+        ),
+    )
+    .unwrap();
+}
+
+/// Runs the binary, with the default config when none was written
+fn execute(server: &mockito::ServerGuard, root: &Path, args: &[&str]) -> std::process::Output {
+    let config = root.join("config.toml");
+    if !config.exists() {
+        write_config(server, root, "");
+    }
+
+    Command::new(env!("CARGO_BIN_EXE_wallheaven_sync"))
+        .arg("--config")
+        .arg(&config)
+        .args(args)
+        .env_remove("WALLHEAVEN_API_KEY")
+        .env_remove("WALLHEAVEN_SYNC_STORAGE_PATH")
+        .env_remove("WALLHEAVEN_SYNC_BASE_URL")
+        .output()
+        .unwrap()
+}
+
+/// Runs the binary and checks that it succeeded
+fn run(server: &mockito::ServerGuard, root: &Path, args: &[&str]) -> std::process::Output {
+    let output = execute(server, root, args);
+
+    assert!(
+        output.status.success(),
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+fn read_index(collection: &Path) -> Value {
+    serde_json::from_str(&fs::read_to_string(collection.join("index.json")).unwrap()).unwrap()
+}
+
+/// Wallpapers of the index
+fn index(collection: &Path) -> Vec<Value> {
+    serde_json::from_value(read_index(collection)["wallpapers"].clone()).unwrap()
+}
+
+#[test]
+fn sync_downloads_every_page_of_the_collection() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    common::collection_page(&mut server, "alice", 7, 1, 2, &["aaa", "bbb"]);
+    common::collection_page(&mut server, "alice", 7, 2, 2, &["ccc"]);
+    for id in ["aaa", "bbb", "ccc"] {
+        common::wallpaper_details(&mut server, id, &["forest"]);
+        common::image(&mut server, id, id.as_bytes());
+    }
+
+    let output = run(&server, root.path(), &["sync", "--all", "--yes"]);
+
+    let collection = root.path().join("storage").join("Nature");
+    for id in ["aaa", "bbb", "ccc"] {
+        let content = fs::read(collection.join(format!("{}.jpg", id))).unwrap();
+        assert_eq!(content, id.as_bytes());
+    }
+
+    let index = index(&collection);
+    assert_eq!(index.len(), 3);
+    assert_eq!(index[0]["source_url"], "https://wallhaven.cc/w/aaa");
+    assert_eq!(index[0]["tags"][0], "forest");
+    assert_eq!(index[0]["purity"], "sfw");
+    assert_eq!(index[0]["dimension_x"], 1920);
+    assert_eq!(index[0]["uploader"], "bob");
+    assert!(index[0].get("source").is_none());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Nature"), "{}", stdout);
+}
+
+#[test]
+fn parallel_sync_downloads_every_wallpaper() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    let ids = ["a1", "a2", "a3", "a4", "a5", "a6", "a7", "a8"];
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    common::collection_page(&mut server, "alice", 7, 1, 1, &ids);
+    for id in ids {
+        common::wallpaper_details(&mut server, id, &[]);
+        common::image(&mut server, id, id.as_bytes());
+    }
+
+    let output = run(
+        &server,
+        root.path(),
+        &["sync", "--all", "--jobs", "4", "--yes"],
+    );
+
+    let collection = root.path().join("storage").join("Nature");
+    for id in ids {
+        let content = fs::read(collection.join(format!("{}.jpg", id))).unwrap();
+        assert_eq!(content, id.as_bytes());
+    }
+    let mut filenames = index(&collection)
+        .iter()
+        .map(|e| e["filename"].as_str().unwrap().to_owned())
+        .collect::<Vec<String>>();
+    filenames.sort();
+    assert_eq!(filenames, ids.map(|id| format!("{}.jpg", id)));
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for index in 1..=ids.len() {
+        let progress = format!("[{}/{}]", index, ids.len());
+        assert_eq!(stdout.matches(&progress).count(), 1, "{}", stdout);
+    }
+}
+
+#[test]
+fn second_sync_downloads_only_new_wallpapers() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    let first_page = common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa"]);
+    common::wallpaper_details(&mut server, "aaa", &[]);
+    let first_image = common::image(&mut server, "aaa", b"aaa").expect(1);
+
+    run(
+        &server,
+        root.path(),
+        &["sync", "--collection", "Nature", "--yes"],
+    );
+
+    first_page.remove();
+    common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa", "bbb"]);
+    common::wallpaper_details(&mut server, "bbb", &[]);
+    let second_image = common::image(&mut server, "bbb", b"bbb").expect(1);
+
+    run(
+        &server,
+        root.path(),
+        &["sync", "--collection", "7", "--yes"],
+    );
+
+    first_image.assert();
+    second_image.assert();
+    let collection = root.path().join("storage").join("Nature");
+    assert_eq!(index(&collection).len(), 2);
+}
+
+#[test]
+fn wallpapers_removed_from_wallhaven_are_archived_or_deleted() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    let listing = common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa", "bbb", "ccc"]);
+    for id in ["aaa", "bbb", "ccc"] {
+        common::wallpaper_details(&mut server, id, &[]);
+        common::image(&mut server, id, id.as_bytes());
+    }
+
+    run(&server, root.path(), &["sync", "--all", "--yes"]);
+    listing.remove();
+    let listing = common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa", "ccc"]);
+
+    let output = run(
+        &server,
+        root.path(),
+        &["sync", "--all", "--deleted", "archive", "--yes"],
+    );
+
+    let collection = root.path().join("storage").join("Nature");
+    assert!(!collection.join("bbb.jpg").exists());
+    assert_eq!(
+        fs::read(collection.join("_archive").join("bbb.jpg")).unwrap(),
+        b"bbb"
+    );
+    let filenames = index(&collection)
+        .iter()
+        .map(|e| e["filename"].as_str().unwrap().to_owned())
+        .collect::<Vec<String>>();
+    assert_eq!(filenames, ["aaa.jpg", "ccc.jpg"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("https://wallhaven.cc/w/bbb"), "{}", stdout);
+
+    listing.remove();
+    common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa"]);
+
+    run(
+        &server,
+        root.path(),
+        &["sync", "--all", "--deleted", "delete", "--yes"],
+    );
+
+    assert!(!collection.join("ccc.jpg").exists());
+    assert!(!collection.join("_archive").join("ccc.jpg").exists());
+    let index = index(&collection);
+    assert_eq!(index.len(), 1);
+    assert_eq!(index[0]["filename"], "aaa.jpg");
+}
+
+#[test]
+fn bare_array_index_is_migrated_with_backup() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa"]);
+    let image = common::image(&mut server, "aaa", b"aaa").expect(0);
+
+    let collection = root.path().join("storage").join("Nature");
+    fs::create_dir_all(&collection).unwrap();
+    fs::write(collection.join("aaa.jpg"), b"aaa").unwrap();
+    fs::write(
+        collection.join("index.json"),
+        r#"[{"filename":"aaa.jpg","tags":[],"source_url":"https://wallhaven.cc/w/aaa","image_url":"x"}]"#,
+    )
+    .unwrap();
+
+    run(&server, root.path(), &["sync", "--all", "--yes"]);
+
+    image.assert();
+    let index = read_index(&collection);
+    assert_eq!(index["schema_version"], 1);
+    assert_eq!(index["collection_id"], 7);
+    assert_eq!(index["username"], "alice");
+    assert!(index["last_synced"].is_string());
+    assert_eq!(index["wallpapers"][0]["filename"], "aaa.jpg");
+
+    let backup = fs::read_to_string(collection.join("index.json.v0.bak")).unwrap();
+    assert!(backup.starts_with("[{"));
+
+    // Backup is not an untracked file of the collection
+    run(&server, root.path(), &["prune", "-c", "Nature", "--yes"]);
+    assert!(collection.join("index.json.v0.bak").exists());
+}
+
+#[test]
+fn index_of_newer_version_is_rejected() {
+    let server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    let collection = root.path().join("storage").join("Nature");
+    fs::create_dir_all(&collection).unwrap();
+    let content = r#"{"schema_version":99,"wallpapers":[]}"#;
+    fs::write(collection.join("index.json"), content).unwrap();
+
+    let output = execute(&server, root.path(), &["verify", "-c", "Nature"]);
+
+    assert_eq!(output.status.code(), Some(12));
+    assert_eq!(
+        fs::read_to_string(collection.join("index.json")).unwrap(),
+        content
+    );
+}
+
+#[test]
+fn failed_download_is_left_out_of_the_index() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa", "bbb"]);
+    common::wallpaper_details(&mut server, "aaa", &[]);
+    common::image(&mut server, "aaa", b"aaa");
+    common::error(&mut server, "/api/v1/w/bbb", 404, "Nothing here");
+
+    let output = run(&server, root.path(), &["sync", "--all", "--yes"]);
+
+    let collection = root.path().join("storage").join("Nature");
+    let index = index(&collection);
+    assert_eq!(index.len(), 1);
+    assert_eq!(index[0]["filename"], "aaa.jpg");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("https://wallhaven.cc/w/bbb"), "{}", stdout);
+}
+
+#[test]
+fn index_keeps_downloads_before_a_failed_one() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    write_config(
+        &server,
+        root.path(),
+        "jobs = 1\n[retry]\nmax_attempts = 2\nbase_delay_ms = 10",
+    );
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa", "bbb", "ccc"]);
+    for id in ["aaa", "bbb", "ccc"] {
+        common::wallpaper_details(&mut server, id, &[]);
+    }
+    common::image(&mut server, "aaa", b"aaa");
+    common::image(&mut server, "bbb", b"bbb");
+    let failing = server
+        .mock("GET", "/full/ccc.jpg")
+        .with_status(500)
+        .expect(2)
+        .create();
+
+    let output = run(&server, root.path(), &["sync", "--all", "--yes"]);
+
+    failing.assert();
+    let collection = root.path().join("storage").join("Nature");
+    let filenames = index(&collection)
+        .iter()
+        .map(|e| e["filename"].as_str().unwrap().to_owned())
+        .collect::<Vec<String>>();
+    assert_eq!(filenames, ["aaa.jpg", "bbb.jpg"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("/full/ccc.jpg"), "{}", stdout);
+}
+
+#[test]
+fn failed_saves_of_the_index_are_reported() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    let listing = common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa"]);
+    for id in ["aaa", "bbb"] {
+        common::wallpaper_details(&mut server, id, &[]);
+        common::image(&mut server, id, id.as_bytes());
+    }
+
+    run(&server, root.path(), &["sync", "--all", "--yes"]);
+    // Temporary file of index.json can't be created in place of a directory
+    let collection = root.path().join("storage").join("Nature");
+    fs::create_dir(collection.join(".index.json.tmp")).unwrap();
+    listing.remove();
+    common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa", "bbb"]);
+
+    let output = run(&server, root.path(), &["sync", "--all", "--yes"]);
+
+    // After the download and by the final save
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.matches("Failed to write").count(), 2, "{}", stdout);
+}
+
+#[test]
+fn partial_files_of_interrupted_run_are_removed() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    let collection = root.path().join("storage").join("Nature");
+    fs::create_dir_all(&collection).unwrap();
+    fs::write(collection.join(".aaa.jpg.tmp"), b"aa").unwrap();
+    fs::write(collection.join(".index.json.tmp"), b"[{").unwrap();
+    fs::write(collection.join(".old.jpg.part"), b"old").unwrap();
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa"]);
+    common::wallpaper_details(&mut server, "aaa", &[]);
+    common::image(&mut server, "aaa", b"aaa");
+
+    run(&server, root.path(), &["sync", "--all", "--yes"]);
+
+    let mut files = fs::read_dir(&collection)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    files.sort();
+    assert_eq!(files, ["aaa.jpg", "index.json"]);
+}
+
+#[test]
+fn unfinished_download_is_resumed() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    let collection = root.path().join("storage").join("Nature");
+    fs::create_dir_all(&collection).unwrap();
+    fs::write(collection.join(".aaa.jpg.part"), b"0123").unwrap();
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa"]);
+    common::wallpaper_details(&mut server, "aaa", &[]);
+    let resumed = server
+        .mock("GET", "/full/aaa.jpg")
+        .match_header("range", "bytes=4-")
+        .with_status(206)
+        .with_header("content-type", "image/jpeg")
+        .with_header("content-range", "bytes 4-9/10")
+        .with_body("456789")
+        .create();
+
+    run(&server, root.path(), &["sync", "--all", "--yes"]);
+
+    resumed.assert();
+    assert_eq!(fs::read(collection.join("aaa.jpg")).unwrap(), b"0123456789");
+    assert!(!collection.join(".aaa.jpg.part").exists());
+}
+
+#[test]
+fn images_over_the_size_limit_are_not_downloaded() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa", "bbb"]);
+    common::wallpaper_details(&mut server, "aaa", &[]);
+    common::wallpaper_details(&mut server, "bbb", &[]);
+    common::image(&mut server, "aaa", b"small");
+    common::image(&mut server, "bbb", b"larger than the limit");
+
+    let output = run(
+        &server,
+        root.path(),
+        &["sync", "--all", "--yes", "--max-size", "10"],
+    );
+
+    let collection = root.path().join("storage").join("Nature");
+    let index = index(&collection);
+    assert_eq!(index.len(), 1);
+    assert_eq!(index[0]["filename"], "aaa.jpg");
+    assert!(!collection.join("bbb.jpg").exists());
+    assert!(!collection.join(".bbb.jpg.part").exists());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("larger than the limit of 10 bytes"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn verify_finds_damaged_files_and_requeues_them_for_rebuild() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa", "bbb", "ccc", "ddd"]);
+    for id in ["aaa", "bbb", "ccc", "ddd"] {
+        common::wallpaper_details(&mut server, id, &[]);
+        common::image(&mut server, id, id.as_bytes());
+    }
+
+    run(&server, root.path(), &["sync", "--all", "--yes"]);
+    let collection = root.path().join("storage").join("Nature");
+    assert_eq!(index(&collection)[0]["size"], 3);
+
+    fs::write(collection.join("aaa.jpg"), b"xyz").unwrap();
+    fs::write(collection.join("bbb.jpg"), b"").unwrap();
+    fs::remove_file(collection.join("ccc.jpg")).unwrap();
+
+    let output = execute(&server, root.path(), &["verify", "-c", "Nature"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout);
+    assert!(stdout.contains("aaa.jpg (checksum mismatch)"), "{}", stdout);
+    assert!(stdout.contains("bbb.jpg (empty file)"), "{}", stdout);
+    assert!(stdout.contains("ccc.jpg (missing)"), "{}", stdout);
+    assert!(stdout.contains("3 of 4 files are damaged"), "{}", stdout);
+
+    run(
+        &server,
+        root.path(),
+        &["verify", "-c", "Nature", "--requeue"],
+    );
+    run(&server, root.path(), &["rebuild", "-c", "Nature"]);
+    run(&server, root.path(), &["verify", "-c", "Nature"]);
+
+    assert_eq!(fs::read(collection.join("aaa.jpg")).unwrap(), b"aaa");
+}
+
+#[cfg(unix)]
+fn inode(path: &Path) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    fs::metadata(path).unwrap().ino()
+}
+
+#[cfg(unix)]
+#[test]
+fn wallpaper_in_several_collections_is_downloaded_once() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    write_config(&server, root.path(), "dedupe = \"hardlink\"");
+    common::collections(&mut server, "alice", &[(7, "Nature"), (8, "Space")]);
+    common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa"]);
+    common::collection_page(&mut server, "alice", 8, 1, 1, &["aaa"]);
+    common::wallpaper_details(&mut server, "aaa", &[]);
+    let image = common::image(&mut server, "aaa", b"aaa").expect(1);
+
+    run(&server, root.path(), &["sync", "--all", "--yes"]);
+
+    image.assert();
+    let storage = root.path().join("storage");
+    let space = index(&storage.join("Space"));
+    let linked = storage
+        .join("Space")
+        .join(space[0]["filename"].as_str().unwrap());
+    assert_eq!(
+        inode(&linked),
+        inode(&storage.join("Nature").join("aaa.jpg"))
+    );
+    assert_eq!(space[0]["size"], 3);
+}
+
+#[cfg(unix)]
+#[test]
+fn linked_wallpaper_keeps_format_of_the_existing_file() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    write_config(&server, root.path(), "dedupe = \"hardlink\"");
+    common::collections(&mut server, "alice", &[(7, "Nature"), (8, "Space")]);
+    common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa"]);
+    common::collection_page(&mut server, "alice", 8, 1, 1, &["aaa"]);
+    common::wallpaper_details(&mut server, "aaa", &[]);
+    let png = b"\x89PNG\r\n\x1a\n rest of the image";
+    let image = common::image_as(&mut server, "aaa", png, "image/png").expect(1);
+
+    run(
+        &server,
+        root.path(),
+        &["sync", "--collection", "Nature", "--yes"],
+    );
+    run(
+        &server,
+        root.path(),
+        &["sync", "--collection", "Space", "--yes"],
+    );
+
+    image.assert();
+    let storage = root.path().join("storage");
+    let space = index(&storage.join("Space"));
+    assert_eq!(space.len(), 1);
+    let filename = space[0]["filename"].as_str().unwrap();
+    assert!(filename.ends_with(".png"), "{}", filename);
+    assert_eq!(
+        inode(&storage.join("Space").join(filename)),
+        inode(&storage.join("Nature").join("aaa.png"))
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn dedupe_links_existing_copies() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::collections(&mut server, "alice", &[(7, "Nature"), (8, "Space")]);
+    common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa", "bbb"]);
+    common::collection_page(&mut server, "alice", 8, 1, 1, &["aaa"]);
+    for id in ["aaa", "bbb"] {
+        common::wallpaper_details(&mut server, id, &[]);
+        common::image(&mut server, id, id.as_bytes());
+    }
+
+    run(&server, root.path(), &["sync", "--all", "--yes"]);
+
+    let output = run(&server, root.path(), &["dedupe"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Linked 1 files, saved 3 bytes"),
+        "{}",
+        stdout
+    );
+
+    let storage = root.path().join("storage");
+    let space = index(&storage.join("Space"));
+    let linked = storage
+        .join("Space")
+        .join(space[0]["filename"].as_str().unwrap());
+    assert_eq!(
+        inode(&linked),
+        inode(&storage.join("Nature").join("aaa.jpg"))
+    );
+
+    let output = run(&server, root.path(), &["dedupe"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Linked 0 files"), "{}", stdout);
+}
+
+#[test]
+fn rename_migrates_collection_to_new_template() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa", "bbb"]);
+    common::wallpaper_details(&mut server, "aaa", &["forest"]);
+    common::wallpaper_details(&mut server, "bbb", &["forest"]);
+    common::image(&mut server, "aaa", b"aaa");
+    common::image(&mut server, "bbb", b"bbb");
+
+    run(&server, root.path(), &["sync", "--all", "--yes"]);
+    run(
+        &server,
+        root.path(),
+        &[
+            "rename",
+            "-c",
+            "Nature",
+            "--naming",
+            "{first_tag}.{ext}",
+            "--yes",
+        ],
+    );
+
+    let collection = root.path().join("storage").join("Nature");
+    let index = index(&collection);
+    assert_eq!(index[0]["filename"], "forest.jpg");
+    assert_eq!(index[1]["filename"], "forest-2.jpg");
+    assert_eq!(fs::read(collection.join("forest.jpg")).unwrap(), b"aaa");
+    assert_eq!(fs::read(collection.join("forest-2.jpg")).unwrap(), b"bbb");
+    assert!(!collection.join("aaa.jpg").exists());
+
+    // Back to the configured naming, the names are swapped through temporary files
+    run(&server, root.path(), &["rename", "-c", "Nature", "--yes"]);
+    assert_eq!(fs::read(collection.join("aaa.jpg")).unwrap(), b"aaa");
+    run(&server, root.path(), &["verify", "-c", "Nature"]);
+}
+
+#[test]
+fn interrupted_rename_is_finished_by_next_run() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa", "bbb"]);
+    for id in ["aaa", "bbb"] {
+        common::wallpaper_details(&mut server, id, &[]);
+        common::image(&mut server, id, id.as_bytes());
+    }
+
+    run(&server, root.path(), &["sync", "--all", "--yes"]);
+
+    // Swap of the names stopped after the first file was moved aside
+    let collection = root.path().join("storage").join("Nature");
+    fs::write(
+        collection.join("index.json.renames"),
+        r#"{"renames":[
+            {"source_url":"https://wallhaven.cc/w/aaa","from":"aaa.jpg","to":"bbb.jpg"},
+            {"source_url":"https://wallhaven.cc/w/bbb","from":"bbb.jpg","to":"aaa.jpg"}
+        ],"staged":false}"#,
+    )
+    .unwrap();
+    fs::rename(
+        collection.join("aaa.jpg"),
+        collection.join(".aaa.jpg.renaming"),
+    )
+    .unwrap();
+
+    run(&server, root.path(), &["sync", "--all", "--yes"]);
+
+    assert_eq!(fs::read(collection.join("bbb.jpg")).unwrap(), b"aaa");
+    assert_eq!(fs::read(collection.join("aaa.jpg")).unwrap(), b"bbb");
+    assert!(!collection.join("index.json.renames").exists());
+    let index = index(&collection);
+    assert_eq!(index[0]["source_url"], "https://wallhaven.cc/w/aaa");
+    assert_eq!(index[0]["filename"], "bbb.jpg");
+    run(&server, root.path(), &["verify", "-c", "Nature"]);
+}
+
+#[test]
+fn rename_keeps_uuid_names() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::collections(&mut server, "alice", &[(8, "Space")]);
+    common::collection_page(&mut server, "alice", 8, 1, 1, &["aaa", "bbb"]);
+    common::wallpaper_details(&mut server, "aaa", &[]);
+    common::wallpaper_details(&mut server, "bbb", &[]);
+    common::image(&mut server, "aaa", b"aaa");
+    common::image(&mut server, "bbb", b"bbb");
+
+    run(&server, root.path(), &["sync", "--all", "--yes"]);
+    let collection = root.path().join("storage").join("Space");
+    let synced = index(&collection);
+
+    let output = run(&server, root.path(), &["rename", "-c", "Space", "--yes"]);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("already follows the naming"), "{}", stdout);
+    assert_eq!(index(&collection), synced);
+}
+
+#[test]
+fn extension_follows_format_of_the_content() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa", "bbb"]);
+    let png = b"\x89PNG\r\n\x1a\n rest of the image";
+    common::wallpaper_details(&mut server, "aaa", &[]);
+    common::image_as(&mut server, "aaa", png, "application/octet-stream");
+    common::wallpaper_details(&mut server, "bbb", &[]);
+    common::image_as(&mut server, "bbb", b"<html>Not found</html>", "text/html");
+
+    let output = run(&server, root.path(), &["sync", "--all", "--yes"]);
+
+    let collection = root.path().join("storage").join("Nature");
+    assert_eq!(fs::read(collection.join("aaa.png")).unwrap(), png);
+    assert!(!collection.join("aaa.jpg").exists());
+    assert!(!collection.join("bbb.jpg").exists());
+
+    let index = index(&collection);
+    assert_eq!(index.len(), 1);
+    assert_eq!(index[0]["filename"], "aaa.png");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("not an image"), "{}", stdout);
+}
+
+#[test]
+fn search_is_saved_and_refreshed_by_sync() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    let first_page = common::search_page(&mut server, "stars", 1, 1, &["aaa"]);
+    for id in ["aaa", "bbb"] {
+        common::wallpaper_details(&mut server, id, &["space"]);
+        common::image(&mut server, id, id.as_bytes());
+    }
+
+    run(
+        &server,
+        root.path(),
+        &[
+            "search",
+            "Space",
+            "--query",
+            "stars",
+            "--max-pages",
+            "2",
+            "--yes",
+        ],
+    );
+
+    let collection = root.path().join("storage").join("Space");
+    let saved = read_index(&collection);
+    assert_eq!(saved["search"]["q"], "stars");
+    assert_eq!(saved["search"]["max_pages"], 2);
+    assert_eq!(saved["wallpapers"].as_array().unwrap().len(), 1);
+
+    first_page.remove();
+    common::search_page(&mut server, "stars", 1, 1, &["bbb", "aaa"]);
+
+    run(
+        &server,
+        root.path(),
+        &["sync", "--search", "Space", "--yes"],
+    );
+
+    assert_eq!(index(&collection).len(), 2);
+    assert_eq!(read_index(&collection)["search"]["q"], "stars");
+}
+
+#[test]
+fn saved_search_is_widened_by_limits() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::search_page(&mut server, "stars", 1, 2, &["aaa"]);
+    common::search_page(&mut server, "stars", 2, 2, &["bbb"]);
+    for id in ["aaa", "bbb"] {
+        common::wallpaper_details(&mut server, id, &["space"]);
+        common::image(&mut server, id, id.as_bytes());
+    }
+
+    run(
+        &server,
+        root.path(),
+        &["search", "Space", "--query", "stars", "--yes"],
+    );
+
+    let collection = root.path().join("storage").join("Space");
+    assert_eq!(index(&collection).len(), 1);
+
+    run(
+        &server,
+        root.path(),
+        &["search", "Space", "--max-pages", "2", "--yes"],
+    );
+
+    let saved = read_index(&collection);
+    assert_eq!(saved["search"]["q"], "stars");
+    assert_eq!(saved["search"]["max_pages"], 2);
+    assert_eq!(saved["wallpapers"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn searches_of_the_config_are_synced_with_all() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    write_config(
+        &server,
+        root.path(),
+        "[search.Top]\nq = \"forest\"\nmax_wallpapers = 1\n",
+    );
+    common::collections(&mut server, "alice", &[]);
+    common::search_page(&mut server, "forest", 1, 1, &["aaa", "bbb"]);
+    common::wallpaper_details(&mut server, "aaa", &["forest"]);
+    common::image(&mut server, "aaa", b"aaa");
+
+    let output = run(&server, root.path(), &["sync", "--all", "--yes"]);
+
+    let index = index(&root.path().join("storage").join("Top"));
+    assert_eq!(index.len(), 1);
+    assert_eq!(index[0]["source_url"], "https://wallhaven.cc/w/aaa");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Synchronizing search: Top"), "{}", stdout);
+}
+
+#[test]
+fn uploads_of_the_user_are_mirrored() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::search_page(&mut server, "@bob", 1, 2, &["aaa"]);
+    common::search_page(&mut server, "@bob", 2, 2, &["bbb"]);
+    for id in ["aaa", "bbb"] {
+        common::wallpaper_details(&mut server, id, &["art"]);
+        common::image(&mut server, id, id.as_bytes());
+    }
+
+    run(
+        &server,
+        root.path(),
+        &["search", "Bob", "--uploader", "bob", "--yes"],
+    );
+
+    let collection = root.path().join("storage").join("Bob");
+    assert_eq!(read_index(&collection)["search"]["uploader"], "bob");
+    assert_eq!(index(&collection).len(), 2);
+
+    // Saved uploader is refreshed without repeating the query
+    run(&server, root.path(), &["search", "Bob", "--yes"]);
+    assert_eq!(index(&collection).len(), 2);
+}
+
+#[test]
+fn fetch_syncs_collection_of_other_user_by_link() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::collections(&mut server, "bob", &[(3, "Cities"), (9, "Nature")]);
+    common::collection_page(&mut server, "bob", 9, 1, 1, &["aaa"]);
+    common::wallpaper_details(&mut server, "aaa", &[]);
+    common::image(&mut server, "aaa", b"aaa");
+
+    run(
+        &server,
+        root.path(),
+        &[
+            "fetch",
+            "https://wallhaven.cc/user/bob/favorites/9",
+            "--yes",
+        ],
+    );
+
+    let collection = root.path().join("storage").join("Nature");
+    assert_eq!(fs::read(collection.join("aaa.jpg")).unwrap(), b"aaa");
+    assert_eq!(read_index(&collection)["username"], "bob");
+    assert_eq!(read_index(&collection)["collection_id"], 9);
+}
+
+#[test]
+fn added_images_with_same_name_are_kept_apart() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    for (path, content) in [("/a/img.jpg", "first"), ("/b/img.jpg", "second")] {
+        server
+            .mock("GET", path)
+            .with_header("content-type", "image/jpeg")
+            .with_body(content)
+            .create();
+    }
+    let collection = root.path().join("storage").join("Nature");
+    fs::create_dir_all(&collection).unwrap();
+
+    for path in ["/a/img.jpg", "/b/img.jpg"] {
+        let url = format!("{}{}", server.url(), path);
+        run(
+            &server,
+            root.path(),
+            &["add", "-c", "Nature", "--url", &url, "--tags", "x"],
+        );
+    }
+
+    let index = index(&collection);
+    assert_eq!(index.len(), 2);
+    assert_eq!(index[0]["filename"], "img.jpg");
+    assert_eq!(index[1]["filename"], "img-2.jpg");
+    assert_eq!(fs::read(collection.join("img.jpg")).unwrap(), b"first");
+    assert_eq!(fs::read(collection.join("img-2.jpg")).unwrap(), b"second");
+    run(&server, root.path(), &["verify", "-c", "Nature"]);
+}
+
+#[test]
+fn fetch_keeps_collection_with_unsafe_label_inside_the_storage() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::collections(&mut server, "bob", &[(9, "../escape")]);
+    common::collection_page(&mut server, "bob", 9, 1, 1, &["aaa"]);
+    common::wallpaper_details(&mut server, "aaa", &[]);
+    common::image(&mut server, "aaa", b"aaa");
+
+    run(
+        &server,
+        root.path(),
+        &[
+            "fetch",
+            "https://wallhaven.cc/user/bob/favorites/9",
+            "--yes",
+        ],
+    );
+
+    assert!(!root.path().join("escape").exists());
+    let collection = root.path().join("storage").join("_escape");
+    let index = index(&collection);
+    assert_eq!(index.len(), 1);
+    let filename = index[0]["filename"].as_str().unwrap();
+    assert_eq!(fs::read(collection.join(filename)).unwrap(), b"aaa");
+}
+
+#[test]
+fn fetch_adds_single_wallpaper_by_link() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::wallpaper_details(&mut server, "aaa", &["forest"]);
+    let image = common::image(&mut server, "aaa", b"aaa").expect(1);
+
+    for _ in 0..2 {
+        run(
+            &server,
+            root.path(),
+            &["fetch", "https://whvn.cc/aaa", "-c", "Nature", "--yes"],
+        );
+    }
+
+    image.assert();
+    let index = index(&root.path().join("storage").join("Nature"));
+    assert_eq!(index.len(), 1);
+    assert_eq!(index[0]["filename"], "aaa.jpg");
+    assert_eq!(index[0]["source_url"], "https://wallhaven.cc/w/aaa");
+    assert_eq!(index[0]["tags"][0], "forest");
+}
+
+#[test]
+fn fetch_mirrors_search_by_link() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::search_page(&mut server, "stars", 1, 1, &["aaa"]);
+    common::wallpaper_details(&mut server, "aaa", &[]);
+    common::image(&mut server, "aaa", b"aaa");
+
+    run(
+        &server,
+        root.path(),
+        &[
+            "fetch",
+            "https://wallhaven.cc/search?q=stars",
+            "-c",
+            "Space",
+            "--yes",
+        ],
+    );
+
+    let collection = root.path().join("storage").join("Space");
+    assert_eq!(read_index(&collection)["search"]["q"], "stars");
+    assert_eq!(index(&collection).len(), 1);
+}
+
+#[test]
+fn filtered_wallpapers_are_skipped_with_the_reason() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    write_config(
+        &server,
+        root.path(),
+        "[collection.Nature.filters]\nmin_width = 2560\npurity = [\"sfw\"]\n",
+    );
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    let url = server.url();
+    let listing = serde_json::json!({
+        "meta": { "last_page": 1 },
+        "data": [
+            { "id": "aaa", "url": "https://wallhaven.cc/w/aaa", "path": format!("{}/full/aaa.jpg", url) },
+            { "id": "bbb", "url": "https://wallhaven.cc/w/bbb", "path": format!("{}/full/bbb.jpg", url), "purity": "nsfw" },
+        ]
+    });
+    server
+        .mock("GET", "/api/v1/collections/alice/7")
+        .match_query(mockito::Matcher::Any)
+        .with_body(listing.to_string())
+        .create();
+    common::wallpaper_details(&mut server, "aaa", &[]);
+    let details = common::wallpaper_details(&mut server, "bbb", &[]).expect(0);
+    let image = common::image(&mut server, "aaa", b"aaa").expect(0);
+
+    let output = run(&server, root.path(), &["sync", "--all", "--yes"]);
+
+    details.assert();
+    image.assert();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Skipped by filters"), "{}", stdout);
+    assert!(
+        stdout.contains("w/aaa (width 1920 is below 2560)"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("w/bbb (purity nsfw is not allowed)"),
+        "{}",
+        stdout
+    );
+    assert!(index(&root.path().join("storage").join("Nature")).is_empty());
+}
+
+#[test]
+fn invalid_filters_are_rejected() {
+    let server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    write_config(
+        &server,
+        root.path(),
+        "[collection.Nature.filters]\nratios = [\"wide\"]\n",
+    );
+
+    let output = execute(&server, root.path(), &["config"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("invalid ratio 'wide'"), "{}", stderr);
+}
+
+#[test]
+fn unknown_names_and_invalid_urls_have_own_exit_codes() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    fs::create_dir_all(root.path().join("storage").join("Nature")).unwrap();
+
+    for (args, code, message) in [
+        (
+            &["sync", "-c", "Cities", "--yes"][..],
+            15,
+            "Collection 'Cities'",
+        ),
+        (&["verify", "-c", "Cities"][..], 15, "Collection 'Cities'"),
+        (&["search", "Space", "--yes"][..], 15, "Search 'Space'"),
+        (
+            &["add", "-c", "Nature", "--url", "no url"][..],
+            16,
+            "Invalid url",
+        ),
+    ] {
+        let output = execute(&server, root.path(), args);
+
+        assert_eq!(output.status.code(), Some(code), "{:?}", args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(message), "{}", stderr);
+    }
+}
+
+#[test]
+fn prompts_fail_when_stdin_is_closed() {
+    let mut server = Server::new();
+    let root = tempfile::tempdir().unwrap();
+    common::collections(&mut server, "alice", &[(7, "Nature")]);
+    let listing = common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa", "bbb"]);
+    for id in ["aaa", "bbb"] {
+        common::wallpaper_details(&mut server, id, &[]);
+        common::image(&mut server, id, id.as_bytes());
+    }
+
+    run(
+        &server,
+        root.path(),
+        &["sync", "--collection", "Nature", "--yes"],
+    );
+
+    let output = execute(&server, root.path(), &["sync"]);
+    assert_eq!(output.status.code(), Some(14));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--collection"), "{}", stderr);
+
+    listing.remove();
+    common::collection_page(&mut server, "alice", 7, 1, 1, &["aaa"]);
+
+    let output = execute(
+        &server,
+        root.path(),
+        &["sync", "--collection", "Nature", "--deleted", "delete"],
+    );
+
+    assert_eq!(output.status.code(), Some(14));
+    let collection = root.path().join("storage").join("Nature");
+    assert!(collection.join("bbb.jpg").exists());
+    assert_eq!(index(&collection).len(), 2);
+}